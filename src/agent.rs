@@ -1,27 +1,28 @@
 use std::path::Path;
 
 use crate::{
-    game::{GridPos, Scene, SnakeOrientation},
-    model::{LinerQNet, QTrainer, Snapshot, SnapshotConcat, ACTION_SIZE, STATE_SIZE},
+    config::AgentConfig,
+    env::{Observation, SnakeEnv, Space},
+    model::{QNet, QTrainer, Snapshot, SnapshotConcat, ACTION_SIZE},
     utils::FixedVecDeque,
     DType, DEVICE,
 };
 
-use rand::{thread_rng, Rng};
+use rand::{rngs::StdRng, Rng, SeedableRng};
 use tch::{
     nn::{Module, VarStore},
     Tensor,
 };
 
-const MAX_MEMORY: usize = 100000;
-const BATCH_SIZE: usize = 1000;
-const LR: f64 = 0.001;
-
 pub struct Agent {
     pub n_games: usize,
     memory: FixedVecDeque<Snapshot>,
     trainer: QTrainer,
+    /// Flattened length of the observations.
+    state_size: usize,
     vs: VarStore,
+    config: AgentConfig,
+    rng: StdRng,
 }
 
 impl std::fmt::Debug for Agent {
@@ -34,81 +35,107 @@ impl std::fmt::Debug for Agent {
 }
 
 impl Agent {
-    pub fn load_if_exists(file_name: &str) -> Self {
+    /// Creates a model sized for the spaces of `env`. Weight initialisation, exploration and
+    /// memory sampling are all determined by `seed`.
+    pub fn new(config: &AgentConfig, seed: u64, env: &impl SnakeEnv) -> Self {
+        tch::manual_seed(seed as i64);
         let vs = VarStore::new(DEVICE);
+        let observation_space = env.observation_space();
+        let Space::Box { shape, .. } = &observation_space else {
+            panic!(
+                "Expected a box observation space, got {:?}",
+                observation_space
+            );
+        };
 
-        let mut exit = Self {
+        Self {
             n_games: 0,
-            memory: FixedVecDeque::new(MAX_MEMORY),
+            memory: FixedVecDeque::new(config.max_memory),
             trainer: QTrainer::new(
                 &vs,
-                LinerQNet::new(&vs, STATE_SIZE as i64, 256, ACTION_SIZE as i64),
-                LR,
-                0.9,
+                QNet::new(
+                    &vs,
+                    shape,
+                    config.hidden_size,
+                    env.action_space().size() as i64,
+                ),
+                config.lr,
+                config.gamma,
             ),
+            state_size: observation_space.size(),
             vs,
-        };
+            config: config.clone(),
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
 
-        let file_name = Path::new("./model").join(file_name);
-        if file_name.exists() {
-            exit.vs.load(file_name).unwrap();
+    /// Loads the model saved at `path`, failing when there is none.
+    pub fn load(
+        path: &Path,
+        config: &AgentConfig,
+        seed: u64,
+        env: &impl SnakeEnv,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        if !path.exists() {
+            return Err(format!("no model at {:?}", path).into());
+        }
+        let mut exit = Self::new(config, seed, env);
+        exit.vs.load(path)?;
+
+        Ok(exit)
+    }
+
+    /// Loads the model at `path`, or creates one with [`Agent::new`] when missing.
+    pub fn load_if_exists(
+        path: &Path,
+        config: &AgentConfig,
+        seed: u64,
+        env: &impl SnakeEnv,
+    ) -> Self {
+        let mut exit = Self::new(config, seed, env);
+        if path.exists() {
+            exit.vs.load(path).unwrap();
         }
 
         exit
     }
 
-    pub fn save(&self, file_name: &str) -> Result<(), Box<dyn std::error::Error>> {
-        let model_folder_path = Path::new("./model");
-        if !model_folder_path.exists() {
-            std::fs::create_dir(model_folder_path)?;
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(model_folder_path) = path.parent() {
+            if !model_folder_path.exists() {
+                std::fs::create_dir_all(model_folder_path)?;
+            }
         }
 
-        let file_name = model_folder_path.join(file_name);
-        Ok(self.vs.save(file_name)?)
+        Ok(self.vs.save(path)?)
     }
 
-    pub fn get_state(scene: &Scene) -> [DType; STATE_SIZE] {
-        let head_pos = &scene.snake_head.ge.pos;
-        let food_pos = &scene.apple.ge.pos;
-
-        let point_l = GridPos::new(head_pos.x - 1, head_pos.y);
-        let point_r = GridPos::new(head_pos.x + 1, head_pos.y);
-        let point_u = GridPos::new(head_pos.x, head_pos.y - 1);
-        let point_d = GridPos::new(head_pos.x, head_pos.y + 1);
-
-        let head_direction = &scene.snake_head.orientation;
-        let dir_l = head_direction == &SnakeOrientation::Left;
-        let dir_r = head_direction == &SnakeOrientation::Right;
-        let dir_u = head_direction == &SnakeOrientation::Up;
-        let dir_d = head_direction == &SnakeOrientation::Down;
-
-        [
-            // Danger straight
-            ((dir_r & scene.is_collision(&point_r))
-                | (dir_l & scene.is_collision(&point_l))
-                | (dir_u & scene.is_collision(&point_u))
-                | (dir_d & scene.is_collision(&point_d))) as u8 as DType,
-            // Danger right
-            ((dir_u & scene.is_collision(&point_r))
-                | (dir_d & scene.is_collision(&point_l))
-                | (dir_l & scene.is_collision(&point_u))
-                | (dir_r & scene.is_collision(&point_d))) as u8 as DType,
-            // Danger left
-            ((dir_d & scene.is_collision(&point_r))
-                | (dir_u & scene.is_collision(&point_l))
-                | (dir_r & scene.is_collision(&point_u))
-                | (dir_l & scene.is_collision(&point_d))) as u8 as DType,
-            // Move head_direction
-            dir_l as u8 as DType,
-            dir_r as u8 as DType,
-            dir_u as u8 as DType,
-            dir_d as u8 as DType,
-            // Food location
-            (food_pos.x < head_pos.x) as u8 as DType, // food left
-            (food_pos.x > head_pos.x) as u8 as DType, // food right
-            (food_pos.y < head_pos.y) as u8 as DType, // food up
-            (food_pos.y > head_pos.y) as u8 as DType, // food down
-        ]
+    /// Deep copy of the weights, to sync another agent through [`Agent::load_weights`].
+    pub fn weights(&self) -> Vec<(String, Tensor)> {
+        tch::no_grad(|| {
+            self.vs
+                .variables()
+                .into_iter()
+                .map(|(name, tensor)| (name, tensor.copy()))
+                .collect()
+        })
+    }
+
+    pub fn load_weights(&mut self, weights: &[(String, Tensor)]) {
+        let mut variables = self.vs.variables();
+        tch::no_grad(|| {
+            for (name, tensor) in weights {
+                variables
+                    .get_mut(name)
+                    .unwrap_or_else(|| panic!("Unknown variable {:?}", name))
+                    .copy_(tensor);
+            }
+        });
+    }
+
+    /// Index, in the action space of the environment, of a one-hot encoded move.
+    pub fn action_index(raw: &[DType; ACTION_SIZE]) -> usize {
+        raw.iter().position(|value| *value == 1.0).unwrap_or(0)
     }
 
     pub fn remember(&mut self, snapshot: Snapshot) {
@@ -116,18 +143,18 @@ impl Agent {
     }
 
     pub fn train_long_memory(&mut self) {
-        let mini_sample = if self.memory.len() > BATCH_SIZE {
-            let mut mini_sample = SnapshotConcat::building(BATCH_SIZE);
-            let mut rng = thread_rng();
+        let batch_size = self.config.batch_size;
+        let mini_sample = if self.memory.len() > batch_size {
+            let mut mini_sample = SnapshotConcat::building(batch_size, self.state_size);
             for index in
-                rand::seq::index::sample(&mut rng, self.memory.len(), BATCH_SIZE).into_iter()
+                rand::seq::index::sample(&mut self.rng, self.memory.len(), batch_size).into_iter()
             {
                 mini_sample.push(&self.memory.as_deque()[index]);
             }
 
             mini_sample
         } else {
-            let mut mini_sample = SnapshotConcat::building(self.memory.len());
+            let mut mini_sample = SnapshotConcat::building(self.memory.len(), self.state_size);
             for snapshot in self.memory.as_deque() {
                 mini_sample.push(snapshot);
             }
@@ -142,28 +169,41 @@ impl Agent {
         if self.memory.len() < count {
             panic!("There are no enough examples.");
         }
-        let mut mini_sample = SnapshotConcat::building(count);
+        let mut mini_sample = SnapshotConcat::building(count, self.state_size);
         for index in self.memory.len() - count..self.memory.len() {
             mini_sample.push(&self.memory.as_deque()[index]);
         }
         self.trainer.train_multiple_steps(mini_sample);
     }
 
-    pub fn get_action(&self, state: &[DType; STATE_SIZE]) -> [DType; ACTION_SIZE] {
-        let mut rng = thread_rng();
+    /// Chooses an action for each row of a `[N, observation size]` batch of observations with a single
+    /// forward pass. While exploring, each action may instead be random.
+    pub fn get_actions(&mut self, states: &Tensor) -> Vec<[DType; ACTION_SIZE]> {
+        let predictions = tch::no_grad(|| self.trainer.model.forward(states).argmax(1, false));
+        let predictions = Vec::<i64>::try_from(predictions).unwrap();
+
+        let epsilon: i32 = self.config.exploration_games - self.n_games as i32;
+        predictions
+            .into_iter()
+            .map(|prediction| {
+                let mut final_move = [0.0, 0.0, 0.0];
+                if self.rng.gen_range(0..self.config.exploration_range) < epsilon {
+                    final_move[self.rng.gen_range(0..2)] = 1.0;
+                } else {
+                    final_move[prediction as usize] = 1.0;
+                }
+                final_move
+            })
+            .collect()
+    }
 
-        let epsilon: i32 = 80 - self.n_games as i32;
+    /// Action with the highest predicted quality, without any exploration.
+    pub fn get_greedy_action(&self, state: &Observation) -> [DType; ACTION_SIZE] {
         let mut final_move = [0.0, 0.0, 0.0];
-        if rng.gen_range(0..200) < epsilon {
-            let target_move = rng.gen_range(0..2);
-            final_move[target_move] = 1.0;
-        } else {
-            let state0 = Tensor::from_slice(state);
-            let prediction = self.trainer.model.forward(&state0);
-            let target_mode = prediction.argmax(0, false).int64_value(&[]);
-            final_move[target_mode as usize] = 1.0;
-        }
-
-        return final_move;
+        let state0 = Tensor::from_slice(state);
+        let prediction = self.trainer.model.forward(&state0);
+        let target_mode = prediction.argmax(0, false).int64_value(&[]);
+        final_move[target_mode as usize] = 1.0;
+        final_move
     }
 }
@@ -0,0 +1,214 @@
+use std::path::{Path, PathBuf};
+
+use bevy::prelude::Resource;
+use clap::{builder::RangedU64ValueParser, Args, Parser, Subcommand};
+use rand::{thread_rng, Rng};
+
+use crate::config::Config;
+
+#[derive(Parser)]
+#[command(version, about = "Train the machine how to play Snake")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+
+    #[command(flatten)]
+    pub settings: RunSettings,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Play the game, with the arrow keys or the controllers and key bindings of the configuration.
+    Play,
+    /// Play against the loaded model, on a second board with the same seed stepping along.
+    Versus,
+    /// Train the agent, rendering every scene unless `--headless` is set.
+    Train {
+        /// Train in a tight loop without opening a window.
+        #[arg(long)]
+        headless: bool,
+
+        /// Threads playing the games of the headless training, overriding the configuration.
+        #[arg(long, requires = "headless")]
+        workers: Option<usize>,
+
+        /// Train every snake of multi-snake games against itself and its past checkpoints.
+        #[arg(long, conflicts_with = "workers")]
+        self_play: bool,
+    },
+    /// Measure a saved model without exploration or training.
+    Eval {
+        /// Number of games to play.
+        #[arg(long, default_value_t = 100)]
+        episodes: usize,
+
+        /// Write a replay of every game to the output directory.
+        #[arg(long)]
+        record: bool,
+    },
+    /// View a replay written by `eval --record`.
+    Watch {
+        /// Replay file to view.
+        replay: PathBuf,
+    },
+}
+
+#[derive(Args, Resource, Clone)]
+pub struct RunSettings {
+    /// Model to load, if it exists.
+    #[arg(long, global = true, default_value = "model/model.ot")]
+    pub model: PathBuf,
+
+    /// Number of games played in parallel, at least one.
+    #[arg(long, global = true, default_value_t = 15, value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    pub scenes: usize,
+
+    /// Seed of every random source of the run; overrides the configured one. At most
+    /// 9223372036854775807, the largest integer a TOML checkpoint can record.
+    #[arg(long, global = true, value_parser = clap::value_parser!(u64).range(..=i64::MAX as u64))]
+    pub seed: Option<u64>,
+
+    /// Directory where checkpoints and replays are written.
+    #[arg(long, global = true, default_value = "model")]
+    pub out_dir: PathBuf,
+
+    /// TOML configuration file; defaults to the one saved next to the model, if any.
+    #[arg(long, global = true)]
+    pub config: Option<PathBuf>,
+
+    /// Arena size as `<width>x<height>`; overrides the configured one.
+    #[arg(long, global = true, value_parser = parse_arena_size)]
+    pub arena: Option<(i32, i32)>,
+
+    /// Wrap the arena borders around instead of walling them, whatever the configuration.
+    #[arg(long, global = true)]
+    pub wrap: bool,
+
+    /// Level file to play on; overrides the configured one.
+    #[arg(long, global = true)]
+    pub level: Option<PathBuf>,
+}
+
+fn parse_arena_size(size: &str) -> Result<(i32, i32), String> {
+    let parse = |value: &str| match value.trim().parse() {
+        Ok(value) if value > 0 => Ok(value),
+        _ => Err(format!(
+            "invalid arena size {:?}, expected <width>x<height>",
+            size
+        )),
+    };
+    let (width, height) = size
+        .split_once('x')
+        .ok_or_else(|| format!("invalid arena size {:?}, expected <width>x<height>", size))?;
+    Ok((parse(width)?, parse(height)?))
+}
+impl RunSettings {
+    /// Loads the configuration and settles the seed of the run: `--seed`, else the configured
+    /// one, else a random one. Both `self` and the returned configuration hold the result, so
+    /// saved checkpoints record it. `--arena`, `--wrap` and `--level` are applied the same way.
+    /// The observation of an existing checkpoint always wins over the configured one.
+    pub fn resolve_config(&mut self) -> Config {
+        let mut config = self.load_config();
+        if let Some((width, height)) = self.arena {
+            config.env.arena_width = width;
+            config.env.arena_height = height;
+        }
+        if self.wrap {
+            config.env.wrap = true;
+        }
+        if let Some(level) = &self.level {
+            config.env.level = Some(level.clone());
+        }
+        self.keep_trained_observation(&mut config);
+        // Random seeds are kept short, TOML integers cannot hold every `u64` anyway. `--seed` is
+        // limited to them.
+        let seed = *self.seed.get_or_insert_with(|| {
+            config
+                .seed
+                .unwrap_or_else(|| thread_rng().gen::<u32>().into())
+        });
+        config.seed = Some(seed);
+        println!("Seed: {}", seed);
+        config
+    }
+
+    /// Loads the configuration given by `--config`, else the one saved with the model, else the
+    /// defaults.
+    fn load_config(&self) -> Config {
+        let path = match &self.config {
+            Some(path) => path.clone(),
+            None => {
+                let path = Config::checkpoint_path(&self.model);
+                if !path.exists() {
+                    return Config::default();
+                }
+                path
+            }
+        };
+        Config::load(&path)
+            .unwrap_or_else(|err| panic!("Failed to load config {:?}: {}", path, err))
+    }
+
+    /// A model only reads the observation it was trained with, so the one recorded with the
+    /// checkpoint replaces the configured one.
+    fn keep_trained_observation(&self, config: &mut Config) {
+        let path = Config::checkpoint_path(&self.model);
+        if self.config.is_none() || !path.exists() {
+            return;
+        }
+        let trained = Config::load(&path)
+            .unwrap_or_else(|err| panic!("Failed to load config {:?}: {}", path, err));
+        if (&trained.env.observation, trained.env.window_size)
+            != (&config.env.observation, config.env.window_size)
+        {
+            println!(
+                "Using the observation {:?} the model was trained with.",
+                trained.env.observation
+            );
+            config.env.observation = trained.env.observation;
+            config.env.window_size = trained.env.window_size;
+        }
+    }
+
+    /// Where checkpoints of the loaded model are saved.
+    pub fn checkpoint_path(&self) -> PathBuf {
+        self.out_dir.join(
+            self.model
+                .file_name()
+                .unwrap_or(Path::new("model.ot").as_os_str()),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_seed(seed: &str) -> Result<Option<u64>, clap::Error> {
+        Cli::try_parse_from(["snake", "--seed", seed, "play"]).map(|cli| cli.settings.seed)
+    }
+
+    #[test]
+    fn seeds_fit_in_a_checkpoint() {
+        let largest = i64::MAX as u64;
+        assert_eq!(parse_seed(&largest.to_string()).unwrap(), Some(largest));
+        assert!(parse_seed(&(largest + 1).to_string()).is_err());
+
+        let config = Config {
+            seed: Some(largest),
+            ..Default::default()
+        };
+        let saved = toml::to_string_pretty(&config).unwrap();
+        assert_eq!(
+            toml::from_str::<Config>(&saved).unwrap().seed,
+            Some(largest)
+        );
+    }
+
+    #[test]
+    fn scenes_are_positive() {
+        let parse = |scenes| Cli::try_parse_from(["snake", "--scenes", scenes, "play"]);
+        assert_eq!(parse("1").unwrap().settings.scenes, 1);
+        assert!(parse("0").is_err());
+    }
+}
@@ -0,0 +1,390 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use bevy::prelude::Resource;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    level::Level,
+    model::REWARD,
+    observation::Encoder,
+    obstacles::ObstacleSettings,
+    simulation::{Arena, FoodSettings, PlayerStepResult},
+};
+
+/// Every tunable of a run. Missing keys in a TOML file fall back to the defaults below.
+#[derive(Serialize, Deserialize, Resource, Clone, Debug, Default)]
+#[serde(default)]
+pub struct Config {
+    /// Seed of every random source, picked at random when missing.
+    pub seed: Option<u64>,
+    pub agent: AgentConfig,
+    pub rewards: RewardConfig,
+    pub env: EnvConfig,
+    pub rollout: RolloutConfig,
+    pub play: PlayConfig,
+    pub selfplay: SelfPlayConfig,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct AgentConfig {
+    /// Snapshots kept in the replay memory, the oldest are dropped first.
+    pub max_memory: usize,
+    /// Snapshots sampled from the memory by each long training.
+    pub batch_size: usize,
+    pub lr: f64,
+    pub gamma: f32,
+    pub hidden_size: i64,
+    /// Number of games after which the agent stops taking random moves.
+    pub exploration_games: i32,
+    /// A random move is taken with probability `(exploration_games - games) / exploration_range`.
+    pub exploration_range: i32,
+}
+impl Default for AgentConfig {
+    fn default() -> Self {
+        Self {
+            max_memory: 100000,
+            batch_size: 1000,
+            lr: 0.001,
+            gamma: 0.9,
+            hidden_size: 256,
+            exploration_games: 80,
+            exploration_range: 200,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct RewardConfig {
+    pub apple: REWARD,
+    pub collision: REWARD,
+    pub starvation: REWARD,
+    pub golden_apple: REWARD,
+    /// Given for any poison apple, whether it shrinks the snake or ends the game.
+    pub poison: REWARD,
+    /// Given when the snake fills the whole arena, which ends the game.
+    pub win: REWARD,
+}
+impl Default for RewardConfig {
+    fn default() -> Self {
+        Self {
+            apple: 10.0,
+            collision: -10.0,
+            starvation: -10.0,
+            golden_apple: 30.0,
+            poison: -10.0,
+            win: 100.0,
+        }
+    }
+}
+
+impl RewardConfig {
+    /// Reward of a step result and whether it ends the game of the snake. [`Nothing`] gives
+    /// nothing, starvation is up to the caller.
+    ///
+    /// [`Nothing`]: PlayerStepResult::Nothing
+    pub fn of(&self, result: PlayerStepResult) -> (REWARD, bool) {
+        match result {
+            PlayerStepResult::Nothing => (0.0, false),
+            PlayerStepResult::AppleEaten => (self.apple, false),
+            PlayerStepResult::GoldenAppleEaten => (self.golden_apple, false),
+            PlayerStepResult::PoisonEaten { fatal } => (self.poison, fatal),
+            PlayerStepResult::Won => (self.win, true),
+            PlayerStepResult::Collision => (self.collision, true),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct EnvConfig {
+    /// A game starves after `starvation_factor * snake length` frames.
+    pub starvation_factor: usize,
+    /// Playable cells per row, without the walls.
+    pub arena_width: i32,
+    /// Playable cells per column, without the walls.
+    pub arena_height: i32,
+    /// Replaces the walls by borders wrapping around to the opposite side.
+    pub wrap: bool,
+    /// Level file to play on, whose grid replaces the arena size.
+    pub level: Option<PathBuf>,
+    /// Fraction of the free cells covered by random obstacles, regenerated every game. Overrides
+    /// the level one when above 0.
+    pub obstacle_density: f32,
+    /// Narrowest gap the random obstacles leave in a row or a column.
+    pub min_corridor_width: i32,
+    /// Apples on the board at once, each respawning on its own when eaten. Overrides the level
+    /// one when above 1.
+    pub apple_count: usize,
+    /// Special apples, overriding the level ones when any chance is above 0.
+    pub food: FoodSettings,
+    /// Snakes sharing an arena without a level file, from 1 to 4.
+    pub snakes: usize,
+    /// Names of the observation encoders the agent sees the board with, concatenated in order:
+    /// `features`, `grid`, `window`, `rays` or `reachable`. Planes, as given by `grid`, train a
+    /// convolutional model.
+    pub observation: Vec<String>,
+    /// Side of the square seen by a `window` observation, odd so that the head is centered.
+    pub window_size: usize,
+}
+impl Default for EnvConfig {
+    fn default() -> Self {
+        Self {
+            starvation_factor: 100,
+            arena_width: 41,
+            arena_height: 41,
+            wrap: false,
+            level: None,
+            obstacle_density: 0.0,
+            min_corridor_width: ObstacleSettings::default().min_corridor_width,
+            apple_count: 1,
+            food: FoodSettings::default(),
+            snakes: 1,
+            observation: vec!["features".to_owned()],
+            window_size: 11,
+        }
+    }
+}
+impl EnvConfig {
+    pub fn arena(&self) -> Arena {
+        Arena::new(self.arena_width, self.arena_height, self.wrap)
+    }
+
+    /// The encoders named by `observation`.
+    pub fn encoder(&self) -> Encoder {
+        Encoder::from_names(&self.observation, self)
+            .unwrap_or_else(|err| panic!("Invalid observation {:?}: {}", self.observation, err))
+    }
+
+    /// The configured level file, else an empty arena, with the configured obstacles, apples and
+    /// food.
+    pub fn level(&self) -> Level {
+        let mut level = match &self.level {
+            Some(path) => Level::load(path, self.wrap)
+                .unwrap_or_else(|err| panic!("Failed to load level {:?}: {}", path, err)),
+            None => Level::empty(self.arena(), self.snakes),
+        };
+        if self.obstacle_density > 0.0 {
+            level.obstacles = ObstacleSettings {
+                density: self.obstacle_density,
+                min_corridor_width: self.min_corridor_width,
+            };
+        }
+        if self.apple_count > 1 {
+            level.apple_count = self.apple_count;
+        }
+        if self.food.golden_chance > 0.0 || self.food.poison_chance > 0.0 {
+            level.food = self.food;
+        }
+        level
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct RolloutConfig {
+    /// Threads playing the games of the headless training; 0 plays them on the learner thread.
+    pub workers: usize,
+    /// Learning steps between two copies of the learner weights to the workers.
+    pub sync_interval: usize,
+}
+impl Default for RolloutConfig {
+    fn default() -> Self {
+        Self {
+            workers: 0,
+            sync_interval: 10,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct SelfPlayConfig {
+    /// Trains by self-play on multi-snake games instead of against scripted opponents.
+    pub enabled: bool,
+    /// Chance of each opponent seat of a game to be taken by a league checkpoint instead of the
+    /// learner itself.
+    pub league_chance: f64,
+    /// Games between two copies of the learner frozen into the league.
+    pub snapshot_interval: usize,
+    /// Checkpoints kept in the league, the oldest leave first.
+    pub league_size: usize,
+    /// Rating of the learner when the league starts.
+    pub initial_rating: f64,
+    /// Largest rating change of a single pairing.
+    pub elo_k: f64,
+}
+impl Default for SelfPlayConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            league_chance: 0.5,
+            snapshot_interval: 200,
+            league_size: 10,
+            initial_rating: 1000.0,
+            elo_k: 16.0,
+        }
+    }
+}
+
+/// Who steers a snake in the `play` mode.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ControllerKind {
+    /// The next unused key bindings.
+    Human,
+    /// The greedy policy of the loaded model.
+    Ai,
+    /// The hand written policy of [`scripted_action`](crate::scripted::scripted_action).
+    Scripted,
+}
+
+/// Keys of a human player, named as Bevy `KeyCode` variants like `KeyW` or `ArrowUp`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct PlayerBindings {
+    pub up: String,
+    pub down: String,
+    pub left: String,
+    pub right: String,
+    /// Gamepad, by connection order, whose d-pad steers as well.
+    pub gamepad: Option<usize>,
+}
+impl PlayerBindings {
+    fn keys(up: &str, down: &str, left: &str, right: &str) -> Self {
+        Self {
+            up: up.to_string(),
+            down: down.to_string(),
+            left: left.to_string(),
+            right: right.to_string(),
+            gamepad: None,
+        }
+    }
+}
+impl Default for PlayerBindings {
+    fn default() -> Self {
+        Self::keys("ArrowUp", "ArrowDown", "ArrowLeft", "ArrowRight")
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct PlayConfig {
+    /// Controller of each snake, in spawn order. Snakes past the end of the list are scripted.
+    pub controllers: Vec<ControllerKind>,
+    /// Boards played side by side on the same seed, each with the controllers of its snakes.
+    /// Replaces `controllers` when not empty.
+    pub boards: Vec<Vec<ControllerKind>>,
+    /// Bindings of the human players, in the order they appear on the boards.
+    pub bindings: Vec<PlayerBindings>,
+    /// Steps every board together, when a human moves, and resets them together once the humans
+    /// are out, so they keep playing the same games.
+    pub lockstep: bool,
+    /// Seconds between two steps of a board at score 0.
+    pub tick_seconds: f32,
+    /// Fraction the tick shortens by for each point of the best score of the board.
+    pub speedup: f32,
+    /// Shortest tick the speedup reaches.
+    pub min_tick_seconds: f32,
+}
+impl Default for PlayConfig {
+    fn default() -> Self {
+        Self {
+            controllers: vec![ControllerKind::Human],
+            boards: Vec::new(),
+            bindings: vec![
+                PlayerBindings::default(),
+                PlayerBindings::keys("KeyW", "KeyS", "KeyA", "KeyD"),
+                PlayerBindings::keys("KeyI", "KeyK", "KeyJ", "KeyL"),
+                PlayerBindings::keys("Numpad8", "Numpad5", "Numpad4", "Numpad6"),
+            ],
+            lockstep: false,
+            tick_seconds: 0.15,
+            speedup: 0.0,
+            min_tick_seconds: 0.05,
+        }
+    }
+}
+impl PlayConfig {
+    /// Time between two steps of a board whose best score is `score`.
+    pub fn tick(&self, score: usize) -> Duration {
+        let seconds = self.tick_seconds * (1.0 - self.speedup).powi(score as i32);
+        Duration::from_secs_f32(seconds.max(self.min_tick_seconds))
+    }
+
+    /// Controllers of the snakes of each board.
+    pub fn boards(&self) -> Vec<Vec<ControllerKind>> {
+        if self.boards.is_empty() {
+            vec![self.controllers.clone()]
+        } else {
+            self.boards.clone()
+        }
+    }
+
+    /// Fails when a board lists more controllers than the `snakes` of its game.
+    pub fn check_boards(&self, snakes: usize) -> Result<(), String> {
+        for (board, kinds) in self.boards().iter().enumerate() {
+            if kinds.len() > snakes {
+                return Err(format!(
+                    "board {} lists {} controllers for {} snakes, raise env.snakes or use a level \
+                     with more spawns",
+                    board,
+                    kinds.len(),
+                    snakes
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(toml::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(folder) = path.parent() {
+            fs::create_dir_all(folder)?;
+        }
+
+        Ok(fs::write(path, toml::to_string_pretty(self)?)?)
+    }
+
+    /// Where the configuration of a checkpoint is saved, next to the model.
+    pub fn checkpoint_path(model_path: &Path) -> std::path::PathBuf {
+        model_path.with_extension("toml")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn boards_need_a_snake_per_controller() {
+        let play: PlayConfig =
+            toml::from_str("boards = [[\"human\"], [\"human\", \"ai\"]]").unwrap();
+        assert_eq!(play.boards().len(), 2);
+        assert!(play.check_boards(2).is_ok());
+        assert!(play.check_boards(1).is_err());
+
+        // Snakes without a controller are scripted.
+        assert!(PlayConfig::default().check_boards(4).is_ok());
+    }
+
+    #[test]
+    fn configured_obstacles_match_level_ones() {
+        let config = EnvConfig {
+            obstacle_density: 0.2,
+            ..Default::default()
+        };
+        let level = Level::parse("obstacle_density: 0.2\n...\n.^.\n...\n...\n", false).unwrap();
+        assert_eq!(config.level().obstacles, level.obstacles);
+    }
+}
@@ -0,0 +1,134 @@
+use crate::{
+    config::{Config, RewardConfig},
+    model::{ACTION_SIZE, REWARD},
+    observation::{Encoder, ObservationEncoder},
+    scripted::with_scripted_opponents,
+    simulation::{PlayerStepAction, PlayerStepResult, Simulation},
+    DType,
+};
+
+/// Flattened observation, shaped as the observation space of its environment.
+pub type Observation = Vec<DType>;
+
+/// Shape of the observations or actions of an environment.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Space {
+    /// One of `n` choices, given as an index.
+    Discrete(usize),
+    /// Values in `low..=high`, flattened from `shape`.
+    Box {
+        shape: Vec<usize>,
+        low: DType,
+        high: DType,
+    },
+}
+impl Space {
+    /// Number of values needed to encode an element of the space: the choices of a discrete
+    /// space, or the flattened length of a box.
+    pub fn size(&self) -> usize {
+        match self {
+            Space::Discrete(n) => *n,
+            Space::Box { shape, .. } => shape.iter().product(),
+        }
+    }
+}
+
+pub struct StepInfo {
+    pub score: usize,
+    pub frame: usize,
+}
+
+pub struct Step {
+    pub observation: Observation,
+    pub reward: REWARD,
+    pub done: bool,
+    pub info: StepInfo,
+}
+
+/// Gym-style environment. Once a step is `done`, the episode must be `reset` before stepping again.
+pub trait SnakeEnv {
+    fn observation_space(&self) -> Space;
+
+    fn action_space(&self) -> Space;
+
+    fn observation(&self) -> Observation;
+
+    fn reset(&mut self) -> Observation;
+
+    fn step(&mut self, action: usize) -> Step;
+}
+
+/// The snake game as an environment: the rules of [`Simulation`] plus the rewards and the
+/// starvation timeout of the training. The agent steers the snake 0, any other snake follows
+/// [`scripted_action`](crate::scripted::scripted_action).
+pub struct SnakeGame {
+    pub sim: Simulation,
+    rewards: RewardConfig,
+    starvation_factor: usize,
+    observation: Encoder,
+}
+
+impl SnakeGame {
+    pub fn new(seed: u64, config: &Config) -> Self {
+        Self {
+            sim: Simulation::new(seed, config.env.level()),
+            rewards: config.rewards.clone(),
+            starvation_factor: config.env.starvation_factor,
+            observation: config.env.encoder(),
+        }
+    }
+
+    pub fn action(index: usize) -> PlayerStepAction {
+        match index {
+            0 => PlayerStepAction::Forward,
+            1 => PlayerStepAction::Left,
+            _ => PlayerStepAction::Right,
+        }
+    }
+}
+
+impl SnakeEnv for SnakeGame {
+    fn observation_space(&self) -> Space {
+        Space::Box {
+            shape: self.observation.shape(self.sim.arena()),
+            low: self.observation.low(),
+            high: 1.0,
+        }
+    }
+
+    fn action_space(&self) -> Space {
+        Space::Discrete(ACTION_SIZE)
+    }
+
+    fn observation(&self) -> Observation {
+        self.observation.encode(&self.sim, 0)
+    }
+
+    fn reset(&mut self) -> Observation {
+        self.sim.reset();
+        self.observation()
+    }
+
+    fn step(&mut self, action: usize) -> Step {
+        let sim = &mut self.sim;
+        let actions = with_scripted_opponents(sim, Self::action(action));
+        let result = sim.step_all(&actions)[0];
+        let starved = sim.frame_iteration > self.starvation_factor * sim.snake(0).len();
+        let (reward, died) = match result {
+            PlayerStepResult::Nothing if starved => (self.rewards.starvation, true),
+            result => self.rewards.of(result),
+        };
+        // The opponents may have eaten the last apple, whatever the first snake did.
+        let done = died || sim.is_over();
+
+        Step {
+            observation: self.observation(),
+            reward,
+            done,
+            info: StepInfo {
+                score: self.sim.snake(0).punctuation,
+                frame: self.sim.frame_iteration,
+            },
+        }
+    }
+}
@@ -0,0 +1,66 @@
+use crate::{
+    agent::Agent,
+    cli::RunSettings,
+    env::{SnakeEnv, SnakeGame},
+    replay::Replay,
+    utils::derive_seed,
+};
+
+/// Plays `episodes` games with the greedy policy of the loaded model and prints the scores,
+/// optionally writing the replay of each game.
+pub fn evaluate(mut settings: RunSettings, episodes: usize, record: bool) {
+    let config = settings.resolve_config();
+    let seed = settings.seed.unwrap();
+
+    let agent = Agent::load(
+        &settings.model,
+        &config.agent,
+        seed,
+        &SnakeGame::new(seed, &config),
+    )
+    .unwrap_or_else(|err| panic!("Failed to load model {:?}: {}", settings.model, err));
+
+    let mut total_score = 0;
+    let mut record_score = 0;
+    for episode in 0..episodes as u64 {
+        let episode_seed = derive_seed(seed, episode);
+        let mut game = SnakeGame::new(episode_seed, &config);
+        let mut replay = Replay::new(episode_seed, game.sim.level().clone());
+
+        let mut observation = game.observation();
+        let info = loop {
+            let action = Agent::action_index(&agent.get_greedy_action(&observation));
+            replay.actions.push(SnakeGame::action(action));
+
+            let step = game.step(action);
+            if step.done {
+                break step.info;
+            }
+            observation = step.observation;
+        };
+
+        total_score += info.score;
+        record_score = record_score.max(info.score);
+        println!(
+            "Game: {}, Score: {}, Frames: {}",
+            episode + 1,
+            info.score,
+            info.frame
+        );
+
+        if record {
+            let path = settings
+                .out_dir
+                .join("replays")
+                .join(format!("{}.replay", episode_seed));
+            replay.save(&path).unwrap();
+        }
+    }
+
+    println!(
+        "Games: {}, Record: {}, Mean Score: {:.2}",
+        episodes,
+        record_score,
+        total_score as f64 / episodes.max(1) as f64
+    );
+}
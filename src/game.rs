@@ -1,159 +1,69 @@
-use std::collections::HashMap;
-
-use crate::{MaterialMesh, ARENA, RECT_SIZE};
+use crate::{
+    simulation::{Apple, Arena, ColliderVariant, FoodKind, GridPos, Simulation},
+    MaterialMesh, RECT_SIZE,
+};
 
 use super::GlobalAssets;
 use bevy::{
+    ecs::system::SystemParam,
     prelude::*,
     sprite::{ColorMaterial, MaterialMesh2dBundle},
 };
-use rand::{thread_rng, Rng};
-
-#[derive(Debug, Eq, PartialEq, Hash, Clone, Copy)]
-pub struct GridPos {
-    pub x: i32,
-    pub y: i32,
-}
 
-impl GridPos {
-    pub fn new(x: i32, y: i32) -> Self {
-        Self { x, y }
-    }
-    fn as_rect_translation(&self) -> Vec3 {
-        Vec3::new(RECT_SIZE * self.x as f32, RECT_SIZE * self.y as f32, 0.0)
-    }
+fn as_rect_translation(pos: &GridPos) -> Vec3 {
+    Vec3::new(RECT_SIZE * pos.x as f32, RECT_SIZE * pos.y as f32, 0.0)
 }
 
-pub struct GridEntity {
-    pub id: Entity,
-    pub pos: GridPos,
-}
-impl GridEntity {
-    fn new(id: Entity, pos: GridPos) -> Self {
-        Self { id, pos }
-    }
-    fn create_raw_bundle(
-        pos: &GridPos,
-        mesh_material: &MaterialMesh,
-    ) -> MaterialMesh2dBundle<ColorMaterial> {
-        let (mesh, material) = mesh_material.clone();
-        MaterialMesh2dBundle {
-            mesh,
-            material,
-            transform: Transform::from_translation(pos.as_rect_translation()),
-            ..default()
-        }
-    }
-
-    fn apply_translation_to(
-        self_pos: &mut GridPos,
-        self_transform: &mut Transform,
-        new_pos: GridPos,
-    ) -> GridPos {
-        let old_head_pos = std::mem::replace(self_pos, new_pos);
-        self_transform.translation = self_pos.as_rect_translation();
-        old_head_pos
-    }
-}
-
-#[derive(PartialEq, Eq, Clone, Copy)]
-pub enum SnakeOrientation {
-    Up,
-    Down,
-    Left,
-    Right,
-}
-impl SnakeOrientation {
-    fn next(&self, pos: &GridPos) -> GridPos {
-        match &self {
-            SnakeOrientation::Up => GridPos::new(pos.x, pos.y + 1),
-            SnakeOrientation::Down => GridPos::new(pos.x, pos.y - 1),
-            SnakeOrientation::Left => GridPos::new(pos.x - 1, pos.y),
-            SnakeOrientation::Right => GridPos::new(pos.x + 1, pos.y),
-        }
-    }
-
-    pub fn opposite(&self) -> Self {
-        match self {
-            Self::Up => Self::Down,
-            Self::Down => Self::Up,
-            Self::Left => Self::Right,
-            Self::Right => Self::Left,
-        }
-    }
-
-    pub fn apply_to_action(&self, applied_orientation: &Self) -> Option<PlayerStepAction> {
-        // Most elegant approach? No, but it's the fastest.
-        match self {
-            Self::Up => match applied_orientation {
-                Self::Up => Some(PlayerStepAction::Forward),
-                Self::Down => None,
-                Self::Left => Some(PlayerStepAction::Left),
-                Self::Right => Some(PlayerStepAction::Right),
-            },
-            Self::Down => match applied_orientation {
-                Self::Up => None,
-                Self::Down => Some(PlayerStepAction::Forward),
-                Self::Left => Some(PlayerStepAction::Right),
-                Self::Right => Some(PlayerStepAction::Left),
-            },
-            Self::Left => match applied_orientation {
-                Self::Up => Some(PlayerStepAction::Right),
-                Self::Down => Some(PlayerStepAction::Left),
-                Self::Left => Some(PlayerStepAction::Forward),
-                Self::Right => None,
-            },
-            Self::Right => match applied_orientation {
-                Self::Up => Some(PlayerStepAction::Left),
-                Self::Down => Some(PlayerStepAction::Right),
-                Self::Left => None,
-                Self::Right => Some(PlayerStepAction::Forward),
-            },
-        }
+fn create_raw_bundle(
+    pos: &GridPos,
+    mesh_material: &MaterialMesh,
+) -> MaterialMesh2dBundle<ColorMaterial> {
+    let (mesh, material) = mesh_material.clone();
+    MaterialMesh2dBundle {
+        mesh,
+        material,
+        transform: Transform::from_translation(as_rect_translation(pos)),
+        ..default()
     }
 }
 
 #[derive(Component)]
 pub struct SnakeHeadMarker;
 impl SnakeHeadMarker {
-    fn create_bundle(assets: &GlobalAssets, pos: &GridPos) -> impl Bundle {
+    fn create_bundle(assets: &GlobalAssets, index: usize, pos: &GridPos) -> impl Bundle {
         (
             SnakeHeadMarker,
-            GridEntity::create_raw_bundle(&pos, &assets.snake_head_mesh_material),
+            create_raw_bundle(pos, &assets.snake_head_mesh_materials[index]),
         )
     }
 }
 
-pub struct SnakeHead {
-    pub orientation: SnakeOrientation,
-    pub ge: GridEntity,
+fn apple_mesh_material<'a>(kind: &FoodKind, assets: &'a GlobalAssets) -> &'a MaterialMesh {
+    match kind {
+        FoodKind::Regular => &assets.apple_mesh_material,
+        FoodKind::Golden => &assets.golden_apple_mesh_material,
+        FoodKind::Poison => &assets.poison_apple_mesh_material,
+    }
 }
 
 #[derive(Component)]
 pub struct AppleMarker;
 impl AppleMarker {
-    fn create_bundle(assets: &GlobalAssets, pos: &GridPos) -> impl Bundle {
+    fn create_bundle(assets: &GlobalAssets, apple: &Apple) -> impl Bundle {
         (
             AppleMarker,
-            GridEntity::create_raw_bundle(&pos, &assets.apple_mesh_material),
+            create_raw_bundle(&apple.pos, apple_mesh_material(&apple.kind, assets)),
         )
     }
 }
 
-pub struct Apple {
-    pub ge: GridEntity,
-}
-
-pub enum ColliderVariant {
-    Wall,
-    SnakeBody,
-}
-impl ColliderVariant {
-    fn mesh_material<'a>(&self, assets: &'a GlobalAssets) -> &'a MaterialMesh {
-        match &self {
-            ColliderVariant::Wall => &assets.wall_mesh_material,
-            ColliderVariant::SnakeBody => &assets.snake_body_mesh_material,
-        }
+fn collider_mesh_material<'a>(
+    variant: &ColliderVariant,
+    assets: &'a GlobalAssets,
+) -> &'a MaterialMesh {
+    match variant {
+        ColliderVariant::Wall => &assets.wall_mesh_material,
+        ColliderVariant::SnakeBody(index) => &assets.snake_body_mesh_materials[*index],
     }
 }
 
@@ -167,197 +77,154 @@ impl ColliderMarker {
     ) -> impl Bundle {
         (
             ColliderMarker,
-            GridEntity::create_raw_bundle(&pos, &variant.mesh_material(&assets)),
+            create_raw_bundle(pos, collider_mesh_material(variant, assets)),
         )
     }
 }
 
-struct Collider {
-    _variant: ColliderVariant,
-    pub ge: GridEntity,
-}
-
-pub enum PlayerStepAction {
-    Forward,
-    Left,
-    Right,
-}
-impl PlayerStepAction {
-    fn rotate(&self, orientation: &SnakeOrientation) -> SnakeOrientation {
-        // Most elegant approach? No, but it's the fastest.
-        match self {
-            PlayerStepAction::Forward => *orientation,
-            PlayerStepAction::Left => match orientation {
-                SnakeOrientation::Up => SnakeOrientation::Left,
-                SnakeOrientation::Down => SnakeOrientation::Right,
-                SnakeOrientation::Left => SnakeOrientation::Down,
-                SnakeOrientation::Right => SnakeOrientation::Up,
-            },
-            PlayerStepAction::Right => match orientation {
-                SnakeOrientation::Up => SnakeOrientation::Right,
-                SnakeOrientation::Down => SnakeOrientation::Left,
-                SnakeOrientation::Left => SnakeOrientation::Up,
-                SnakeOrientation::Right => SnakeOrientation::Down,
-            },
-        }
-    }
-}
-
-pub enum PlayerStepResult {
-    Nothing,
-    AppleEaten,
-    Collision,
-}
-
+/// Bevy view of a [`Simulation`], holding the entities that render it. The simulation itself is
+/// owned by whoever drives the scene.
 #[derive(Component)]
 pub struct Scene {
     self_entity: Entity,
-    pub snake_head: SnakeHead,
-    snake_body_parts: Vec<GridPos>,
-    pub apple: Apple,
-    colliders: HashMap<GridPos, Collider>,
+    snakes: Vec<SceneSnake>,
+    apples: Vec<Entity>,
+    obstacles: Vec<Entity>,
+    /// Obstacles of the game the `obstacles` entities render.
+    obstacle_positions: Vec<GridPos>,
+    pub arena: Arena,
     pub frame_iteration: usize,
-    pub punctuation: usize,
+    /// Score of each snake.
+    pub scores: Vec<usize>,
+}
+
+struct SceneSnake {
+    head: Entity,
+    body_parts: Vec<Entity>,
 }
 impl Scene {
-    fn push_collider(
-        &mut self,
+    fn spawn_collider(
+        &self,
         commands: &mut Commands,
         assets: &GlobalAssets,
         variant: ColliderVariant,
-        pos: GridPos,
-    ) {
+        pos: &GridPos,
+    ) -> Entity {
         let collider_id = commands
-            .spawn(ColliderMarker::create_bundle(&variant, &assets, &pos))
+            .spawn(ColliderMarker::create_bundle(&variant, assets, pos))
             .id();
-        if let Some(replaced) = self.colliders.insert(
-            pos,
-            Collider {
-                _variant: variant,
-                ge: GridEntity::new(collider_id, pos),
-            },
-        ) {
-            panic!("Collider override. Replaced: {:?}", replaced.ge.pos)
-        }
         commands.entity(self.self_entity).add_child(collider_id);
+        collider_id
     }
 
-    pub fn snake_len(&self) -> usize {
-        self.snake_body_parts.len() + 1
-    }
-
-    pub fn is_collision(&self, pos: &GridPos) -> bool {
-        self.colliders.contains_key(pos)
+    fn spawn_apple(&self, commands: &mut Commands, assets: &GlobalAssets, apple: &Apple) -> Entity {
+        let apple_id = commands
+            .spawn(AppleMarker::create_bundle(assets, apple))
+            .id();
+        commands.entity(self.self_entity).add_child(apple_id);
+        apple_id
     }
 
-    pub fn play_step(
+    fn spawn_obstacles(
         &mut self,
         commands: &mut Commands,
         assets: &GlobalAssets,
-        collider_query: &mut Query<&mut Transform, With<ColliderMarker>>,
-        snake_transform: &mut Transform,
-        apple_transform: &mut Transform,
-        action: PlayerStepAction,
-    ) -> PlayerStepResult {
-        let orientation = action.rotate(&self.snake_head.orientation);
-        let new_head_pos = orientation.next(&self.snake_head.ge.pos);
-        if self.colliders.contains_key(&new_head_pos) {
-            PlayerStepResult::Collision
-        } else if self.apple.ge.pos == new_head_pos {
-            self.snake_head.orientation = orientation;
-            let old_head_pos = GridEntity::apply_translation_to(
-                &mut self.snake_head.ge.pos,
-                snake_transform,
-                new_head_pos,
-            );
-            self.push_collider(commands, &assets, ColliderVariant::SnakeBody, old_head_pos);
-            self.snake_body_parts.push(old_head_pos);
-
-            let apple_pos = &mut self.apple.ge.pos;
-
-            let mut rng = thread_rng();
-
-            let mut new_apple_pos = *apple_pos;
-            while self.colliders.contains_key(&new_apple_pos) || new_apple_pos == new_head_pos {
-                new_apple_pos = GridPos::new(
-                    rng.gen_range(ARENA.min.x..=ARENA.max.x),
-                    rng.gen_range(ARENA.min.y..=ARENA.max.y),
-                );
-            }
-            GridEntity::apply_translation_to(apple_pos, apple_transform, new_apple_pos);
-
-            self.punctuation += 1;
-            self.frame_iteration += 1;
-            PlayerStepResult::AppleEaten
-        } else {
-            let self_pos = &mut self.snake_head.ge.pos;
-            self.snake_head.orientation = orientation;
-
-            let old_head_pos =
-                GridEntity::apply_translation_to(self_pos, snake_transform, new_head_pos);
-
-            let last_body_part_id = self
-                .colliders
-                .remove(&self.snake_body_parts.remove(0))
-                .unwrap();
-            let mut last_body_part = collider_query.get_mut(last_body_part_id.ge.id).unwrap();
-
-            last_body_part.translation = old_head_pos.as_rect_translation();
-
-            self.colliders.insert(old_head_pos, last_body_part_id);
-            self.snake_body_parts.push(old_head_pos);
-
-            self.frame_iteration += 1;
-            PlayerStepResult::Nothing
+        sim: &Simulation,
+    ) {
+        for pos in sim.obstacles() {
+            let id = self.spawn_collider(commands, assets, ColliderVariant::Wall, pos);
+            self.obstacles.push(id);
         }
+        self.obstacle_positions = sim.obstacles().to_vec();
     }
 
-    pub fn reset(
-        &mut self,
-        commands: &mut Commands,
-        assets: &GlobalAssets,
-        snake_head_transform: &mut Transform,
-        apple_transform: &mut Transform,
-    ) {
-        let mut rng = thread_rng();
+    /// Moves the rendered entities to match the simulation, spawning or despawning snake body
+    /// parts and apples when their count changed, and obstacles when a new game generated
+    /// others.
+    pub fn sync(&mut self, sim: &Simulation, view: &mut SceneView) {
+        self.frame_iteration = sim.frame_iteration;
+        self.scores = sim.snakes().iter().map(|snake| snake.punctuation).collect();
 
-        for body_pos in &self.snake_body_parts {
-            let body = self.colliders.remove(body_pos).unwrap();
-            commands.entity(body.ge.id).despawn();
+        if self.obstacle_positions != sim.obstacles() {
+            for removed in self.obstacles.drain(..) {
+                view.commands.entity(removed).despawn();
+            }
+            self.spawn_obstacles(&mut view.commands, &view.assets, sim);
         }
-        self.snake_body_parts.clear();
 
-        self.snake_head.orientation = SnakeOrientation::Up;
-
-        self.snake_head.ge.pos = GridPos::new(0, 0);
-        for _ in 0..2 {
-            let snake_head = &mut self.snake_head;
-            let new_head_pos = snake_head.orientation.next(&snake_head.ge.pos);
-            let old_head_pos = std::mem::replace(&mut snake_head.ge.pos, new_head_pos);
-            self.push_collider(commands, &assets, ColliderVariant::SnakeBody, old_head_pos);
-            self.snake_body_parts.push(old_head_pos);
+        let apple_count = sim.apples().len();
+        for removed in self.apples.drain(apple_count.min(self.apples.len())..) {
+            view.commands.entity(removed).despawn();
+        }
+        for (index, apple) in sim.apples().iter().enumerate() {
+            if let Some(id) = self.apples.get(index) {
+                let (mut transform, mut material) = view.apple_query.get_mut(*id).unwrap();
+                transform.translation = as_rect_translation(&apple.pos);
+                *material = apple_mesh_material(&apple.kind, &view.assets).1.clone();
+            } else {
+                let id = self.spawn_apple(&mut view.commands, &view.assets, apple);
+                self.apples.push(id);
+            }
         }
 
-        let snake_pos = self.snake_head.ge.pos;
-        GridEntity::apply_translation_to(
-            &mut self.snake_head.ge.pos,
-            snake_head_transform,
-            snake_pos,
-        );
-        GridEntity::apply_translation_to(
-            &mut self.apple.ge.pos,
-            apple_transform,
-            GridPos::new(
-                rng.gen_range(ARENA.min.x..=ARENA.max.x),
-                rng.gen_range(ARENA.min.y..=ARENA.max.y),
-            ),
-        );
-
-        self.frame_iteration = 0;
-        self.punctuation = 0;
+        for (snake_index, snake) in sim.snakes().iter().enumerate() {
+            let (mut head_transform, mut head_visibility) = view
+                .snake_head_query
+                .get_mut(self.snakes[snake_index].head)
+                .unwrap();
+            head_transform.translation = as_rect_translation(&snake.head.pos);
+            *head_visibility = if snake.alive {
+                Visibility::Inherited
+            } else {
+                Visibility::Hidden
+            };
+
+            let body_parts = &mut self.snakes[snake_index].body_parts;
+            let body_len = snake.body_parts().len();
+            for removed in body_parts.drain(body_len.min(body_parts.len())..) {
+                view.commands.entity(removed).despawn();
+            }
+            for (index, pos) in snake.body_parts().iter().enumerate() {
+                if let Some(id) = self.snakes[snake_index].body_parts.get(index) {
+                    let mut transform = view.collider_query.get_mut(*id).unwrap();
+                    transform.translation = as_rect_translation(pos);
+                } else {
+                    let id = self.spawn_collider(
+                        &mut view.commands,
+                        &view.assets,
+                        ColliderVariant::SnakeBody(snake_index),
+                        pos,
+                    );
+                    self.snakes[snake_index].body_parts.push(id);
+                }
+            }
+        }
     }
 }
 
+type SnakeHeadFilter = (
+    With<SnakeHeadMarker>,
+    Without<AppleMarker>,
+    Without<ColliderMarker>,
+);
+type AppleFilter = (
+    With<AppleMarker>,
+    Without<SnakeHeadMarker>,
+    Without<ColliderMarker>,
+);
+
+/// Everything [`Scene::sync`] needs to update the rendered entities.
+#[derive(SystemParam)]
+pub struct SceneView<'w, 's> {
+    commands: Commands<'w, 's>,
+    assets: Res<'w, GlobalAssets>,
+    snake_head_query:
+        Query<'w, 's, (&'static mut Transform, &'static mut Visibility), SnakeHeadFilter>,
+    apple_query:
+        Query<'w, 's, (&'static mut Transform, &'static mut Handle<ColorMaterial>), AppleFilter>,
+    collider_query: Query<'w, 's, &'static mut Transform, With<ColliderMarker>>,
+}
+
 #[derive(Bundle)]
 struct SceneBundle {
     scene: Scene,
@@ -373,67 +240,55 @@ pub fn init_scene(
     commands: &mut Commands,
     assets: &Res<GlobalAssets>,
     transform: Transform,
+    sim: &Simulation,
 ) -> Entity {
-    let mut rng = thread_rng();
-
-    let snake_head_id = commands.spawn_empty().id();
-    let snake_head = SnakeHead {
-        orientation: SnakeOrientation::Up,
-        ge: GridEntity::new(snake_head_id, GridPos::new(0, 0)),
-    };
-
-    let apple_id = commands.spawn_empty().id();
-    let apple = Apple {
-        ge: GridEntity::new(
-            apple_id,
-            GridPos::new(
-                rng.gen_range(ARENA.min.x..=ARENA.max.x),
-                rng.gen_range(ARENA.min.y..=ARENA.max.y),
-            ),
-        ),
-    };
-
+    let snake_head_ids: Vec<Entity> = sim
+        .snakes()
+        .iter()
+        .enumerate()
+        .map(|(index, snake)| {
+            commands
+                .spawn(SnakeHeadMarker::create_bundle(
+                    assets,
+                    index,
+                    &snake.head.pos,
+                ))
+                .id()
+        })
+        .collect();
     let scene_id = commands.spawn_empty().id();
     let mut scene = Scene {
         self_entity: scene_id,
-        snake_head,
-        apple,
-        snake_body_parts: Vec::new(),
-        colliders: HashMap::new(),
-        frame_iteration: 0,
-        punctuation: 0,
+        snakes: snake_head_ids
+            .iter()
+            .map(|head| SceneSnake {
+                head: *head,
+                body_parts: Vec::new(),
+            })
+            .collect(),
+        apples: Vec::new(),
+        obstacles: Vec::new(),
+        obstacle_positions: Vec::new(),
+        arena: *sim.arena(),
+        frame_iteration: sim.frame_iteration,
+        scores: sim.snakes().iter().map(|snake| snake.punctuation).collect(),
     };
 
-    let mut walls = Vec::new();
-    for x in ARENA.min.x - 1..=ARENA.max.x + 1 {
-        walls.push((x, ARENA.min.y - 1));
-        walls.push((x, ARENA.max.y + 1));
+    for pos in sim.walls() {
+        scene.spawn_collider(commands, assets, ColliderVariant::Wall, pos);
     }
-    for y in (ARENA.min.y - 1 + 1)..ARENA.max.y + 1 {
-        walls.push((ARENA.min.x - 1, y));
-        walls.push((ARENA.max.x + 1, y));
+    scene.spawn_obstacles(commands, assets, sim);
+    for apple in sim.apples() {
+        let id = scene.spawn_apple(commands, assets, apple);
+        scene.apples.push(id);
     }
-    for (x, y) in walls {
-        scene.push_collider(commands, &assets, ColliderVariant::Wall, GridPos::new(x, y));
-    }
-
-    for _ in 0..2 {
-        let snake_head = &mut scene.snake_head;
-        let new_head_pos = snake_head.orientation.next(&snake_head.ge.pos);
-        let old_head_pos = std::mem::replace(&mut snake_head.ge.pos, new_head_pos);
-        scene.push_collider(commands, &assets, ColliderVariant::SnakeBody, old_head_pos);
-        scene.snake_body_parts.push(old_head_pos);
+    for (index, snake) in sim.snakes().iter().enumerate() {
+        for pos in snake.body_parts() {
+            let id = scene.spawn_collider(commands, assets, ColliderVariant::SnakeBody(index), pos);
+            scene.snakes[index].body_parts.push(id);
+        }
     }
 
-    commands
-        .entity(snake_head_id)
-        .insert(SnakeHeadMarker::create_bundle(
-            &assets,
-            &scene.snake_head.ge.pos,
-        ));
-    commands
-        .entity(apple_id)
-        .insert(AppleMarker::create_bundle(&assets, &scene.apple.ge.pos));
     commands
         .entity(scene_id)
         .insert(SceneBundle {
@@ -444,8 +299,7 @@ pub fn init_scene(
             inherited_visibility: Default::default(),
             view_visibility: Default::default(),
         })
-        .add_child(snake_head_id)
-        .add_child(apple_id);
+        .push_children(&snake_head_ids);
 
     scene_id
 }
@@ -0,0 +1,171 @@
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+
+use crate::{
+    config::PlayerBindings,
+    simulation::{PlayerStepAction, SnakeOrientation},
+};
+
+/// Turns a player can press ahead of the ticks applying them.
+const INPUT_BUFFER: usize = 2;
+
+/// Keys, and optionally the d-pad of a gamepad, steering the snake of a human player.
+pub struct HumanController {
+    up_command: KeyCode,
+    down_command: KeyCode,
+    left_command: KeyCode,
+    right_command: KeyCode,
+    gamepad: Option<Gamepad>,
+    /// Turns pressed since the last ticks, applied one per tick.
+    buffer: VecDeque<SnakeOrientation>,
+}
+impl HumanController {
+    pub fn from_bindings(bindings: &PlayerBindings) -> Result<Self, String> {
+        Ok(Self {
+            up_command: parse_key_code(&bindings.up)?,
+            down_command: parse_key_code(&bindings.down)?,
+            left_command: parse_key_code(&bindings.left)?,
+            right_command: parse_key_code(&bindings.right)?,
+            gamepad: bindings.gamepad.map(Gamepad::new),
+            buffer: VecDeque::with_capacity(INPUT_BUFFER),
+        })
+    }
+
+    /// Buffers the turn pressed this frame, if any, unless it keeps or reverses the direction
+    /// the snake will have after the turns already buffered. `current` is the direction of the
+    /// snake now.
+    pub fn read_input(
+        &mut self,
+        keyboard_input: &ButtonInput<KeyCode>,
+        gamepad_input: &ButtonInput<GamepadButton>,
+        current: SnakeOrientation,
+    ) {
+        let Some(pressed) = self.orientation_pressed(keyboard_input, gamepad_input) else {
+            return;
+        };
+        let planned = self.buffer.back().copied().unwrap_or(current);
+        if self.buffer.len() < INPUT_BUFFER && pressed != planned && pressed != planned.opposite() {
+            self.buffer.push_back(pressed);
+        }
+    }
+
+    /// Action of the next tick: the oldest buffered turn, else keeping the direction.
+    pub fn next_action(&mut self, current: &SnakeOrientation) -> PlayerStepAction {
+        self.buffer
+            .pop_front()
+            .and_then(|orientation| current.apply_to_action(&orientation))
+            .unwrap_or(PlayerStepAction::Forward)
+    }
+
+    /// Drops the buffered turns, when the game restarts.
+    pub fn clear(&mut self) {
+        self.buffer.clear();
+    }
+
+    fn orientation_pressed(
+        &self,
+        keyboard_input: &ButtonInput<KeyCode>,
+        gamepad_input: &ButtonInput<GamepadButton>,
+    ) -> Option<SnakeOrientation> {
+        let pressed = |key: KeyCode, button: GamepadButtonType| {
+            keyboard_input.just_pressed(key)
+                || self.gamepad.is_some_and(|gamepad| {
+                    gamepad_input.just_pressed(GamepadButton::new(gamepad, button))
+                })
+        };
+
+        if pressed(self.up_command, GamepadButtonType::DPadUp) {
+            Some(SnakeOrientation::Up)
+        } else if pressed(self.down_command, GamepadButtonType::DPadDown) {
+            Some(SnakeOrientation::Down)
+        } else if pressed(self.left_command, GamepadButtonType::DPadLeft) {
+            Some(SnakeOrientation::Left)
+        } else if pressed(self.right_command, GamepadButtonType::DPadRight) {
+            Some(SnakeOrientation::Right)
+        } else {
+            None
+        }
+    }
+}
+
+const LETTERS: [KeyCode; 26] = [
+    KeyCode::KeyA,
+    KeyCode::KeyB,
+    KeyCode::KeyC,
+    KeyCode::KeyD,
+    KeyCode::KeyE,
+    KeyCode::KeyF,
+    KeyCode::KeyG,
+    KeyCode::KeyH,
+    KeyCode::KeyI,
+    KeyCode::KeyJ,
+    KeyCode::KeyK,
+    KeyCode::KeyL,
+    KeyCode::KeyM,
+    KeyCode::KeyN,
+    KeyCode::KeyO,
+    KeyCode::KeyP,
+    KeyCode::KeyQ,
+    KeyCode::KeyR,
+    KeyCode::KeyS,
+    KeyCode::KeyT,
+    KeyCode::KeyU,
+    KeyCode::KeyV,
+    KeyCode::KeyW,
+    KeyCode::KeyX,
+    KeyCode::KeyY,
+    KeyCode::KeyZ,
+];
+
+const DIGITS: [KeyCode; 10] = [
+    KeyCode::Digit0,
+    KeyCode::Digit1,
+    KeyCode::Digit2,
+    KeyCode::Digit3,
+    KeyCode::Digit4,
+    KeyCode::Digit5,
+    KeyCode::Digit6,
+    KeyCode::Digit7,
+    KeyCode::Digit8,
+    KeyCode::Digit9,
+];
+
+const NUMPAD_DIGITS: [KeyCode; 10] = [
+    KeyCode::Numpad0,
+    KeyCode::Numpad1,
+    KeyCode::Numpad2,
+    KeyCode::Numpad3,
+    KeyCode::Numpad4,
+    KeyCode::Numpad5,
+    KeyCode::Numpad6,
+    KeyCode::Numpad7,
+    KeyCode::Numpad8,
+    KeyCode::Numpad9,
+];
+
+/// Key named as its [`KeyCode`] variant: `KeyA` to `KeyZ`, `Digit0` to `Digit9`, `Numpad0` to
+/// `Numpad9` or one of the arrows, like `ArrowUp`.
+fn parse_key_code(name: &str) -> Result<KeyCode, String> {
+    let indexed = |prefix: &str, range: char| {
+        let rest = name.strip_prefix(prefix)?;
+        let mut chars = rest.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => Some((c as u32).checked_sub(range as u32)? as usize),
+            _ => None,
+        }
+    };
+
+    let key = match name {
+        "ArrowUp" => Some(KeyCode::ArrowUp),
+        "ArrowDown" => Some(KeyCode::ArrowDown),
+        "ArrowLeft" => Some(KeyCode::ArrowLeft),
+        "ArrowRight" => Some(KeyCode::ArrowRight),
+        _ => indexed("Key", 'A')
+            .and_then(|index| LETTERS.get(index))
+            .or_else(|| indexed("Digit", '0').and_then(|index| DIGITS.get(index)))
+            .or_else(|| indexed("Numpad", '0').and_then(|index| NUMPAD_DIGITS.get(index)))
+            .copied(),
+    };
+    key.ok_or_else(|| format!("unknown key {:?}", name))
+}
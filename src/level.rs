@@ -0,0 +1,433 @@
+use std::{fmt, fs, io, path::Path};
+
+use crate::{
+    obstacles::ObstacleSettings,
+    simulation::{Arena, FoodSettings, GridPos, SnakeOrientation, MAX_SNAKES},
+};
+
+/// Layout a game starts from: the arena, the walls inside it, where the snake spawns and,
+/// optionally, where the apples appear.
+///
+/// Level files are plain text. Lines starting with `;` are comments, `key: value` lines are
+/// headers and every other line is a row of the grid, from the top:
+///
+/// ```text
+/// ; A corridor
+/// apples: 1,1 8,1
+/// ##########
+/// #........#
+/// ####.#####
+/// #...^....#
+/// ##########
+/// ```
+///
+/// `#` is a wall, `.` (or a space) an empty cell and one of `^`, `v`, `<`, `>` the head of a
+/// snake, facing that way, with its body behind. Up to four heads make as many snakes, numbered
+/// in reading order. The `apples` header lists `column,row` cells,
+/// counted from the top left corner, where apples appear in that order, skipping occupied
+/// cells. Without it, apples appear on random free cells. An `apple_count` header sets how many
+/// apples are on the board at once, 1 by default. A `wrap: true` header makes the
+/// borders wrap around, `obstacle_density` and `min_corridor_width` headers add random
+/// obstacles to every game, and `golden: <chance> <score> <lifetime>` and
+/// `poison: <chance> <shrink>` headers enable the special apples.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Level {
+    pub arena: Arena,
+    pub walls: Vec<GridPos>,
+    /// Where each snake starts, one per snake.
+    pub spawns: Vec<Spawn>,
+    pub apples: Vec<GridPos>,
+    /// Apples on the board at once.
+    pub apple_count: usize,
+    pub food: FoodSettings,
+    pub obstacles: ObstacleSettings,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Spawn {
+    pub head: GridPos,
+    pub orientation: SnakeOrientation,
+}
+impl Spawn {
+    /// Cells of the initial snake, from the tail to the head.
+    pub fn snake_cells(&self, arena: &Arena) -> [GridPos; 3] {
+        let backwards = self.orientation.opposite();
+        let body = arena.next(&self.head, &backwards);
+        [arena.next(&body, &backwards), body, self.head]
+    }
+}
+
+impl Level {
+    /// `arena` without walls inside, with `snakes` snakes spawning upwards, side by side around
+    /// the center.
+    pub fn empty(arena: Arena, snakes: usize) -> Self {
+        assert!(
+            (1..=MAX_SNAKES).contains(&snakes),
+            "A game has 1 to {} snakes, got {}.",
+            MAX_SNAKES,
+            snakes
+        );
+        assert!(
+            arena.height() >= 3
+                && arena.width() >= snakes as i32
+                && arena.width() * arena.height() > 3 * snakes as i32,
+            "The arena must fit the initial snakes and an apple, got {}x{}.",
+            arena.width(),
+            arena.height()
+        );
+        let spawns = (1..=snakes as i32)
+            .map(|index| Spawn {
+                head: GridPos::new(
+                    arena.min.x + index * arena.width() / (snakes as i32 + 1),
+                    0.min(arena.max.y - 2) + 2,
+                ),
+                orientation: SnakeOrientation::Up,
+            })
+            .collect();
+        Self {
+            arena,
+            walls: Vec::new(),
+            spawns,
+            apples: Vec::new(),
+            apple_count: 1,
+            food: FoodSettings::default(),
+            obstacles: ObstacleSettings::default(),
+        }
+    }
+
+    /// Cells of every initial snake.
+    pub fn snake_cells(&self) -> Vec<GridPos> {
+        self.spawns
+            .iter()
+            .flat_map(|spawn| spawn.snake_cells(&self.arena))
+            .collect()
+    }
+
+    /// Loads a level file. `wrap` wraps the borders around even if the file does not.
+    pub fn load(path: &Path, wrap: bool) -> io::Result<Self> {
+        Self::parse(&fs::read_to_string(path)?, wrap)
+    }
+
+    pub fn parse(content: &str, mut wrap: bool) -> io::Result<Self> {
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+
+        let mut apple_cells = Vec::new();
+        let mut apple_count = 1;
+        let mut food = FoodSettings::default();
+        let mut obstacles = ObstacleSettings::default();
+        let mut rows = Vec::new();
+        // An empty line ends the grid, while a line of spaces is a row of empty cells.
+        let mut grid_ended = false;
+        for line in content.lines() {
+            if line.is_empty() {
+                grid_ended = !rows.is_empty();
+                continue;
+            }
+            if line.starts_with(';') {
+                continue;
+            }
+            if let Some((key, value)) = line.split_once(':') {
+                match key.trim() {
+                    "wrap" => {
+                        wrap |= value
+                            .trim()
+                            .parse::<bool>()
+                            .map_err(|_| invalid(format!("invalid wrap {:?}", value.trim())))?
+                    }
+                    "apples" => {
+                        for cell in value.split_whitespace() {
+                            let parsed = cell
+                                .split_once(',')
+                                .and_then(|(column, row)| {
+                                    Some((column.parse().ok()?, row.parse().ok()?))
+                                })
+                                .ok_or_else(|| invalid(format!("invalid apple cell {:?}", cell)))?;
+                            apple_cells.push(parsed);
+                        }
+                    }
+                    "apple_count" => {
+                        apple_count = value.trim().parse().map_err(|_| {
+                            invalid(format!("invalid apple count {:?}", value.trim()))
+                        })?
+                    }
+                    "golden" => {
+                        let values: Vec<&str> = value.split_whitespace().collect();
+                        let parsed = match values[..] {
+                            [chance, score, lifetime] => chance
+                                .parse()
+                                .ok()
+                                .zip(score.parse().ok())
+                                .zip(lifetime.parse().ok()),
+                            _ => None,
+                        };
+                        let ((chance, score), lifetime) = parsed.ok_or_else(|| {
+                            invalid(format!("invalid golden apples {:?}", value.trim()))
+                        })?;
+                        food.golden_chance = chance;
+                        food.golden_score = score;
+                        food.golden_lifetime = lifetime;
+                    }
+                    "poison" => {
+                        let values: Vec<&str> = value.split_whitespace().collect();
+                        let parsed = match values[..] {
+                            [chance, shrink] => chance.parse().ok().zip(shrink.parse().ok()),
+                            _ => None,
+                        };
+                        let (chance, shrink) = parsed.ok_or_else(|| {
+                            invalid(format!("invalid poison apples {:?}", value.trim()))
+                        })?;
+                        food.poison_chance = chance;
+                        food.poison_shrink = shrink;
+                    }
+                    "obstacle_density" => {
+                        obstacles.density = value.trim().parse().map_err(|_| {
+                            invalid(format!("invalid obstacle density {:?}", value.trim()))
+                        })?
+                    }
+                    "min_corridor_width" => {
+                        obstacles.min_corridor_width = value.trim().parse().map_err(|_| {
+                            invalid(format!("invalid corridor width {:?}", value.trim()))
+                        })?
+                    }
+                    key => return Err(invalid(format!("unknown header {:?}", key))),
+                }
+            } else if grid_ended {
+                return Err(invalid(format!(
+                    "empty line inside the grid, above row {:?}",
+                    line
+                )));
+            } else {
+                rows.push(line.chars().collect::<Vec<_>>());
+            }
+        }
+
+        let height = rows.len() as i32;
+        let width = rows.iter().map(Vec::len).max().unwrap_or(0) as i32;
+        if width == 0 {
+            return Err(invalid("the level has no grid".to_string()));
+        }
+        let arena = Arena::new(width, height, wrap);
+        let cell_pos =
+            |column: i32, row: i32| GridPos::new(arena.min.x + column, arena.max.y - row);
+
+        let mut walls = Vec::new();
+        let mut spawns = Vec::new();
+        for (row, cells) in rows.iter().enumerate() {
+            for (column, cell) in cells.iter().enumerate() {
+                let pos = cell_pos(column as i32, row as i32);
+                let orientation = match cell {
+                    '#' => {
+                        walls.push(pos);
+                        continue;
+                    }
+                    '.' | ' ' => continue,
+                    '^' => SnakeOrientation::Up,
+                    'v' => SnakeOrientation::Down,
+                    '<' => SnakeOrientation::Left,
+                    '>' => SnakeOrientation::Right,
+                    other => return Err(invalid(format!("unknown cell {:?}", other))),
+                };
+                spawns.push(Spawn {
+                    head: pos,
+                    orientation,
+                });
+            }
+        }
+        if !(1..=MAX_SNAKES).contains(&spawns.len()) {
+            return Err(invalid(format!(
+                "the level needs 1 to {} spawns, got {}",
+                MAX_SNAKES,
+                spawns.len()
+            )));
+        }
+
+        let mut apples = Vec::new();
+        for (column, row) in apple_cells {
+            if !(0..width).contains(&column) || !(0..height).contains(&row) {
+                return Err(invalid(format!(
+                    "apple cell {},{} is outside the grid",
+                    column, row
+                )));
+            }
+            apples.push(cell_pos(column, row));
+        }
+
+        let level = Self {
+            arena,
+            walls,
+            spawns,
+            apples,
+            apple_count,
+            food,
+            obstacles,
+        };
+        level.validate()?;
+        Ok(level)
+    }
+
+    /// Checks that the snakes and the apples have room to spawn.
+    fn validate(&self) -> io::Result<()> {
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message);
+
+        let in_arena = |pos: &GridPos| self.arena.contains(pos);
+        let snake_cells = self.snake_cells();
+        let overlaps = snake_cells
+            .iter()
+            .enumerate()
+            .any(|(index, pos)| snake_cells[index + 1..].contains(pos));
+        if overlaps
+            || snake_cells
+                .iter()
+                .any(|pos| !in_arena(pos) || self.walls.contains(pos))
+        {
+            return Err(invalid("the snake body behind a spawn is blocked"));
+        }
+        if self.apples.iter().any(|pos| self.walls.contains(pos)) {
+            return Err(invalid("an apple cell is a wall"));
+        }
+        let cells = (self.arena.width() * self.arena.height()) as usize;
+        if cells <= self.walls.len() + snake_cells.len() {
+            return Err(invalid("the level has no room for an apple"));
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Level {
+    /// Writes the level in the format read by [`Level::parse`].
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let arena = &self.arena;
+        if arena.wrap {
+            writeln!(f, "wrap: true")?;
+        }
+        if !self.apples.is_empty() {
+            let cells: Vec<String> = self
+                .apples
+                .iter()
+                .map(|pos| format!("{},{}", pos.x - arena.min.x, arena.max.y - pos.y))
+                .collect();
+            writeln!(f, "apples: {}", cells.join(" "))?;
+        }
+        if self.apple_count != 1 {
+            writeln!(f, "apple_count: {}", self.apple_count)?;
+        }
+        let food = &self.food;
+        if food.golden_chance > 0.0 {
+            writeln!(
+                f,
+                "golden: {} {} {}",
+                food.golden_chance, food.golden_score, food.golden_lifetime
+            )?;
+        }
+        if food.poison_chance > 0.0 {
+            writeln!(f, "poison: {} {}", food.poison_chance, food.poison_shrink)?;
+        }
+        if self.obstacles.density > 0.0 {
+            writeln!(f, "obstacle_density: {}", self.obstacles.density)?;
+            writeln!(
+                f,
+                "min_corridor_width: {}",
+                self.obstacles.min_corridor_width
+            )?;
+        }
+        for y in (arena.min.y..=arena.max.y).rev() {
+            let row: String = (arena.min.x..=arena.max.x)
+                .map(|x| {
+                    let pos = GridPos::new(x, y);
+                    if let Some(spawn) = self.spawns.iter().find(|spawn| spawn.head == pos) {
+                        match spawn.orientation {
+                            SnakeOrientation::Up => '^',
+                            SnakeOrientation::Down => 'v',
+                            SnakeOrientation::Left => '<',
+                            SnakeOrientation::Right => '>',
+                        }
+                    } else if self.walls.contains(&pos) {
+                        '#'
+                    } else {
+                        '.'
+                    }
+                })
+                .collect();
+            writeln!(f, "{}", row)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(content: &str) -> String {
+        Level::parse(content, false).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn parse_reads_the_grid_from_the_top() {
+        let level = Level::parse("apples: 3,0\n#...\n#.^.\n#...\n#...\n", false).unwrap();
+        assert_eq!((level.arena.width(), level.arena.height()), (4, 4));
+        let cell = |column, row| GridPos::new(level.arena.min.x + column, level.arena.max.y - row);
+        assert_eq!(
+            level.walls,
+            (0..4).map(|row| cell(0, row)).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            level.spawns,
+            [Spawn {
+                head: cell(2, 1),
+                orientation: SnakeOrientation::Up
+            }]
+        );
+        assert_eq!(level.snake_cells(), [cell(2, 3), cell(2, 2), cell(2, 1)]);
+        assert_eq!(level.apples, [cell(3, 0)]);
+    }
+
+    #[test]
+    fn parse_keeps_rows_of_spaces() {
+        let level = Level::parse("\n#.^.\n    \n#...\n#\n", false).unwrap();
+        assert_eq!((level.arena.width(), level.arena.height()), (4, 4));
+        let cell = |column, row| GridPos::new(level.arena.min.x + column, level.arena.max.y - row);
+        assert_eq!(level.walls, [cell(0, 0), cell(0, 2), cell(0, 3)]);
+        assert_eq!(level.spawns[0].head, cell(2, 0));
+    }
+
+    #[test]
+    fn parse_rejects_invalid_levels() {
+        assert!(error("; nothing\n").contains("no grid"));
+        assert!(error("....\n").contains("spawns"));
+        assert!(error("^^^^^\n.....\n.....\n.....\n").contains("spawns"));
+        assert!(error(".x.\n.^.\n...\n...\n").contains("unknown cell"));
+        assert!(error("speed: 2\n.^.\n...\n...\n").contains("unknown header"));
+        assert!(error("apples: 3,0\n.^.\n...\n...\n").contains("outside the grid"));
+        assert!(error("apples: 0,0\n#^.\n...\n...\n").contains("is a wall"));
+        assert!(error("...\n...\n.^.\n").contains("blocked"));
+        assert!(error("^\n.\n.\n").contains("no room"));
+        assert!(error(".^.\n...\n\n...\n").contains("empty line"));
+    }
+
+    #[test]
+    fn display_writes_what_parse_reads() {
+        let content = "\
+; Every header
+apples: 1,1 4,3
+apple_count: 2
+golden: 0.25 5 40
+poison: 0.125 2
+obstacle_density: 0.2
+min_corridor_width: 2
+######
+#....#
+#.^..#
+#....#
+#..v.#
+#....#
+#....#
+######
+";
+        for wrap in [false, true] {
+            let level = Level::parse(content, wrap).unwrap();
+            assert_eq!(level.spawns.len(), 2);
+            assert_eq!(Level::parse(&level.to_string(), false).unwrap(), level);
+        }
+    }
+}
@@ -1,30 +1,51 @@
 mod agent;
+mod cli;
+mod config;
+mod env;
+mod eval;
 mod game;
+mod input;
+mod level;
 mod model;
+mod observation;
+mod obstacles;
+mod replay;
+mod rollout;
+mod scripted;
+mod selfplay;
+mod simulation;
+mod train;
 mod utils;
+mod vec_env;
 
 use std::sync::Mutex;
 
 use agent::Agent;
-use bevy::{prelude::*, sprite::Mesh2dHandle};
+use bevy::{prelude::*, render::camera::ScalingMode, sprite::Mesh2dHandle};
 use bevy_egui::{
     egui::{self, Id},
     EguiContexts, EguiPlugin,
 };
+use clap::Parser;
+use cli::{Cli, Command, RunSettings};
+use config::{Config, ControllerKind, PlayConfig};
 use egui_plot::{AxisHints, Legend, Line, Plot, PlotPoints};
-use game::{
-    init_scene, AppleMarker, ColliderMarker, PlayerStepAction, PlayerStepResult, Scene,
-    SnakeHeadMarker, SnakeOrientation,
-};
-use model::{Snapshot, ACTION_SIZE};
+use env::SnakeGame;
+use eval::evaluate;
+use game::{init_scene, Scene, SceneView};
+use input::HumanController;
+use observation::{Encoder, ObservationEncoder};
+use replay::Replay;
+use scripted::{scripted_action, with_scripted_opponents};
+use selfplay::SelfPlaySession;
+use simulation::{Arena, PlayerStepAction, PlayerStepResult, Simulation};
 use tch::Device;
+use train::{train_headless, FinishedGame, TrainingSession};
+use utils::derive_seed;
+use vec_env::VecEnv;
 
 pub const RECT_SIZE: f32 = 5.0;
 pub const DEVICE: Device = Device::Cpu;
-pub const ARENA: IRect = IRect {
-    min: IVec2 { x: -20, y: -20 },
-    max: IVec2 { x: 20, y: 20 },
-};
 
 pub type MaterialMesh = (Mesh2dHandle, Handle<ColorMaterial>);
 pub type DType = f32;
@@ -32,33 +53,132 @@ pub type DType = f32;
 #[derive(Resource)]
 pub struct GlobalAssets {
     apple_mesh_material: MaterialMesh,
+    golden_apple_mesh_material: MaterialMesh,
+    poison_apple_mesh_material: MaterialMesh,
     wall_mesh_material: MaterialMesh,
-    snake_body_mesh_material: MaterialMesh,
-    snake_head_mesh_material: MaterialMesh,
+    /// Body color of each snake.
+    snake_body_mesh_materials: Vec<MaterialMesh>,
+    /// Head color of each snake.
+    snake_head_mesh_materials: Vec<MaterialMesh>,
+}
+
+/// Simulation of a scene driven directly, without an environment.
+#[derive(Component)]
+struct SceneSimulation(Simulation);
+
+enum SnakeController {
+    Human(HumanController),
+    /// Behind a mutex as the model cannot be shared between threads.
+    Ai(Box<Mutex<Agent>>),
+    Scripted,
 }
 
+/// Controllers of every snake of a played scene. The game steps on a timer, shortening as the
+/// scores grow when the configuration asks for it.
 #[derive(Component)]
-struct HumanController {
-    up_command: KeyCode,
-    down_command: KeyCode,
-    left_command: KeyCode,
-    right_command: KeyCode,
+struct PlayController {
+    /// Index of the board, in the order of the configuration.
+    board: usize,
+    controllers: Vec<SnakeController>,
+    /// How the AI controllers see the board.
+    observation: Encoder,
+    timer: Timer,
+}
+impl PlayController {
+    fn has_humans(&self) -> bool {
+        self.controllers
+            .iter()
+            .any(|controller| matches!(controller, SnakeController::Human(_)))
+    }
+
+    fn humans_alive(&self, sim: &Simulation) -> bool {
+        self.controllers
+            .iter()
+            .zip(sim.snakes())
+            .any(|(controller, snake)| {
+                snake.alive && matches!(controller, SnakeController::Human(_))
+            })
+    }
+
+    /// Buffers the turns the humans pressed this frame.
+    fn read_input(
+        &mut self,
+        keyboard_input: &ButtonInput<KeyCode>,
+        gamepad_input: &ButtonInput<GamepadButton>,
+        sim: &Simulation,
+    ) {
+        for (controller, snake) in self.controllers.iter_mut().zip(sim.snakes()) {
+            if let SnakeController::Human(human) = controller {
+                human.read_input(keyboard_input, gamepad_input, snake.head.orientation);
+            }
+        }
+    }
+
+    /// Advances the timer, paced by the best score of the board. Returns whether it ticked.
+    fn tick(&mut self, time: &Time, play: &PlayConfig, sim: &Simulation) -> bool {
+        let best_score = sim
+            .snakes()
+            .iter()
+            .map(|snake| snake.punctuation)
+            .max()
+            .unwrap_or(0);
+        self.timer.set_duration(play.tick(best_score));
+        self.timer.tick(time.delta()).just_finished()
+    }
+
+    /// Actions of every snake for the next step, consuming one buffered turn per human.
+    fn actions(&mut self, sim: &Simulation) -> Vec<PlayerStepAction> {
+        self.controllers
+            .iter_mut()
+            .enumerate()
+            .map(|(index, controller)| match controller {
+                SnakeController::Human(human) => {
+                    human.next_action(&sim.snake(index).head.orientation)
+                }
+                SnakeController::Ai(agent) => SnakeGame::action(Agent::action_index(
+                    &agent
+                        .get_mut()
+                        .unwrap()
+                        .get_greedy_action(&self.observation.encode(sim, index)),
+                )),
+                SnakeController::Scripted => scripted_action(sim, index),
+            })
+            .collect()
+    }
+
+    /// Restarts the game of the board.
+    fn reset(&mut self, sim: &mut Simulation) {
+        print_game_over(self.board, sim);
+        sim.reset();
+        for controller in &mut self.controllers {
+            if let SnakeController::Human(human) = controller {
+                human.clear();
+            }
+        }
+    }
+}
+
+/// Games trained on by the windowed training.
+enum Trainer {
+    /// The agent plays the snake 0 of every game.
+    Single {
+        session: Box<TrainingSession>,
+        envs: VecEnv<SnakeGame>,
+    },
+    SelfPlay(Box<SelfPlaySession>),
 }
-impl HumanController {
-    pub fn orientation_pressed(
-        &self,
-        keyboard_input: &Res<ButtonInput<KeyCode>>,
-    ) -> Option<SnakeOrientation> {
-        if keyboard_input.just_pressed(self.up_command) {
-            Some(SnakeOrientation::Up)
-        } else if keyboard_input.just_pressed(self.down_command) {
-            Some(SnakeOrientation::Down)
-        } else if keyboard_input.just_pressed(self.left_command) {
-            Some(SnakeOrientation::Left)
-        } else if keyboard_input.just_pressed(self.right_command) {
-            Some(SnakeOrientation::Right)
-        } else {
-            None
+impl Trainer {
+    fn step(&mut self) -> Vec<FinishedGame> {
+        match self {
+            Trainer::Single { session, envs } => session.step(envs),
+            Trainer::SelfPlay(session) => session.step(),
+        }
+    }
+
+    fn sim(&self, index: usize) -> &Simulation {
+        match self {
+            Trainer::Single { envs, .. } => &envs.envs()[index].sim,
+            Trainer::SelfPlay(session) => session.sim(index),
         }
     }
 }
@@ -67,39 +187,74 @@ impl HumanController {
 struct AiController {
     plot_scores: Vec<[f64; 2]>,
     plot_mean_scores: Vec<[f64; 2]>,
-    total_score: usize,
-    record: usize,
-    agent: Mutex<Agent>,
-}
-impl AiController {
-    fn action(raw: &[DType; ACTION_SIZE]) -> PlayerStepAction {
-        if raw[0] == 1.0 {
-            PlayerStepAction::Forward
-        } else if raw[1] == 1.0 {
-            PlayerStepAction::Left
-        } else {
-            PlayerStepAction::Right
-        }
-    }
+    trainer: Mutex<Trainer>,
 }
 
+/// Scene rendering the game at this index of the [`AiController`] environments.
 #[derive(Component)]
-struct AiControllerDependent;
+struct AiControllerDependent(usize);
+
+#[derive(Resource)]
+struct WatchedReplay(Replay);
+
+#[derive(Component)]
+struct ReplayController {
+    cursor: usize,
+    timer: Timer,
+}
 
 fn main() {
-    let use_human_controller = false;
+    let Cli {
+        command,
+        mut settings,
+    } = Cli::parse();
+
+    match command {
+        Command::Train {
+            headless: true,
+            workers,
+            self_play,
+        } => return train_headless(settings, workers, self_play),
+        Command::Eval { episodes, record } => return evaluate(settings, episodes, record),
+        _ => {}
+    }
+
+    let mut config = settings.resolve_config();
+    if let Command::Train {
+        self_play: true, ..
+    } = command
+    {
+        config.selfplay.enabled = true;
+    }
+    if let Command::Versus = command {
+        config.play.boards = vec![vec![ControllerKind::Human], vec![ControllerKind::Ai]];
+        config.play.lockstep = true;
+    }
 
     let mut app = App::default();
     app.add_plugins(DefaultPlugins);
     app.add_plugins(EguiPlugin);
     app.add_systems(Update, ui_info_update);
-    if use_human_controller {
-        app.add_systems(Startup, (init_assets, init_human).chain());
-        app.add_systems(Update, human_update);
-    } else {
-        app.add_systems(Startup, (init_assets, init_ai).chain());
-        app.add_systems(Update, ai_update);
+    match command {
+        Command::Play | Command::Versus => {
+            app.add_systems(Startup, (init_assets, init_human).chain());
+            app.add_systems(Update, play_update);
+        }
+        Command::Train { .. } => {
+            app.add_systems(Startup, (init_assets, init_ai).chain());
+            app.add_systems(Update, ai_update);
+        }
+        Command::Watch { replay } => {
+            let replay = Replay::load(&replay)
+                .unwrap_or_else(|err| panic!("Failed to load replay {:?}: {}", replay, err));
+            app.insert_resource(WatchedReplay(replay));
+            app.add_systems(Startup, (init_assets, init_replay).chain());
+            app.add_systems(Update, replay_update);
+        }
+        Command::Eval { .. } => unreachable!(),
     }
+    app.insert_resource(config);
+    app.insert_resource(settings);
     app.run();
 }
 
@@ -115,21 +270,52 @@ fn init_assets(
             Mesh2dHandle(meshes.add(generic_rect)),
             materials.add(Color::srgb(1.0, 0.0, 0.0)),
         ),
-        wall_mesh_material: (
+        golden_apple_mesh_material: (
             Mesh2dHandle(meshes.add(generic_rect)),
-            materials.add(Color::srgb(0.0, 0.0, 1.0)),
+            materials.add(Color::srgb(1.0, 0.8, 0.0)),
         ),
-        snake_body_mesh_material: (
+        poison_apple_mesh_material: (
             Mesh2dHandle(meshes.add(generic_rect)),
-            materials.add(Color::srgb(0.0, 1.0, 1.0)),
+            materials.add(Color::srgb(0.6, 0.0, 0.8)),
         ),
-        snake_head_mesh_material: (
+        wall_mesh_material: (
             Mesh2dHandle(meshes.add(generic_rect)),
-            materials.add(Color::srgb(0.0, 1.0, 0.0)),
+            materials.add(Color::srgb(0.0, 0.0, 1.0)),
         ),
+        snake_body_mesh_materials: [
+            Color::srgb(0.0, 1.0, 1.0),
+            Color::srgb(1.0, 0.6, 0.0),
+            Color::srgb(1.0, 0.6, 0.8),
+            Color::srgb(0.8, 0.8, 0.8),
+        ]
+        .into_iter()
+        .map(|color| (Mesh2dHandle(meshes.add(generic_rect)), materials.add(color)))
+        .collect(),
+        snake_head_mesh_materials: [
+            Color::srgb(0.0, 1.0, 0.0),
+            Color::srgb(1.0, 1.0, 0.0),
+            Color::srgb(1.0, 0.0, 1.0),
+            Color::srgb(0.5, 0.5, 0.5),
+        ]
+        .into_iter()
+        .map(|color| (Mesh2dHandle(meshes.add(generic_rect)), materials.add(color)))
+        .collect(),
     });
 }
 
+/// Spawns a camera zoomed to show at least `scenes` (columns, rows) of `arena` around the origin.
+fn spawn_camera(commands: &mut Commands, arena: &Arena, scenes: Vec2, margin: f32) {
+    let scene_size = Vec2::new(arena.width() as f32 + 2.0, arena.height() as f32 + 2.0);
+    let extent = scene_size * RECT_SIZE * scenes * margin;
+
+    let mut camera = Camera2dBundle::default();
+    camera.projection.scaling_mode = ScalingMode::AutoMin {
+        min_width: extent.x,
+        min_height: extent.y,
+    };
+    commands.spawn(camera);
+}
+
 fn ui_info_update(
     mut ctx: EguiContexts,
     camera_query: Query<(&Camera, &GlobalTransform)>,
@@ -138,21 +324,25 @@ fn ui_info_update(
     let mut area_id: u64 = 1;
     let (camera, camera_transform) = camera_query.single();
     for (scene, scene_transform) in scene_query.iter() {
+        let top_left = Vec3::new(
+            scene.arena.min.x as f32 * RECT_SIZE,
+            scene.arena.max.y as f32 * RECT_SIZE,
+            0.0,
+        );
         if let Some(point) =
-            camera.world_to_viewport(camera_transform, scene_transform.translation())
+            camera.world_to_viewport(camera_transform, scene_transform.translation() + top_left)
         {
             area_id += 1;
             egui::Area::new(Id::new(area_id))
-                .fixed_pos([
-                    point.x + ARENA.min.x as f32 * RECT_SIZE,
-                    point.y + ARENA.min.y as f32 * RECT_SIZE,
-                ])
+                .fixed_pos([point.x, point.y])
                 .show(ctx.ctx_mut(), |ui| {
                     let frame = egui::Frame::none();
 
                     frame.show(ui, |ui| {
                         ui.set_min_width(200.0);
-                        ui.label(format!("Score: {}", scene.punctuation));
+                        let scores: Vec<String> =
+                            scene.scores.iter().map(ToString::to_string).collect();
+                        ui.label(format!("Score: {}", scores.join(" | ")));
                         ui.label(format!("Frame: {}", scene.frame_iteration));
                     });
                 });
@@ -160,229 +350,292 @@ fn ui_info_update(
     }
 }
 
-fn init_human(mut commands: Commands, assets: Res<GlobalAssets>) {
-    commands.spawn(Camera2dBundle::default());
+fn init_human(
+    mut commands: Commands,
+    assets: Res<GlobalAssets>,
+    settings: Res<RunSettings>,
+    config: Res<Config>,
+) {
+    let seed = settings.seed.unwrap();
+    let boards = config.play.boards();
+    let level = config.env.level();
+    let arena = level.arena;
+    config
+        .play
+        .check_boards(level.spawns.len())
+        .unwrap_or_else(|err| panic!("Invalid play boards: {}", err));
 
-    let scene_id = init_scene(&mut commands, &assets, Transform::default());
-    commands.entity(scene_id).insert(HumanController {
-        up_command: KeyCode::ArrowUp,
-        down_command: KeyCode::ArrowDown,
-        left_command: KeyCode::ArrowLeft,
-        right_command: KeyCode::ArrowRight,
-    });
+    let margin = 1.1;
+    spawn_camera(
+        &mut commands,
+        &arena,
+        Vec2::new(boards.len() as f32, 1.0),
+        margin,
+    );
+
+    let mut bindings = config.play.bindings.iter();
+    for (board, kinds) in boards.iter().enumerate() {
+        // Every board plays the same game.
+        let game = SnakeGame::new(seed, &config);
+        let x_index = board as f32 - (boards.len() - 1) as f32 / 2.0;
+        let scene_id = init_scene(
+            &mut commands,
+            &assets,
+            Transform::from_xyz(
+                RECT_SIZE * (arena.width() + 2) as f32 * x_index * margin,
+                0.0,
+                0.0,
+            ),
+            &game.sim,
+        );
+
+        let controllers: Vec<SnakeController> = (0..game.sim.snakes().len())
+            .map(|index| {
+                match kinds
+                    .get(index)
+                    .copied()
+                    .unwrap_or(ControllerKind::Scripted)
+                {
+                    ControllerKind::Human => {
+                        let player = bindings
+                            .next()
+                            .expect("Missing key bindings for a human player, see play.bindings.");
+                        SnakeController::Human(
+                            HumanController::from_bindings(player)
+                                .unwrap_or_else(|err| panic!("Invalid key bindings: {}", err)),
+                        )
+                    }
+                    ControllerKind::Ai => SnakeController::Ai(Box::new(Mutex::new(
+                        Agent::load(&settings.model, &config.agent, seed, &game).unwrap_or_else(
+                            |err| panic!("Failed to load model {:?}: {}", settings.model, err),
+                        ),
+                    ))),
+                    ControllerKind::Scripted => SnakeController::Scripted,
+                }
+            })
+            .collect();
+        commands.entity(scene_id).insert((
+            SceneSimulation(game.sim),
+            PlayController {
+                board,
+                controllers,
+                observation: config.env.encoder(),
+                timer: Timer::new(config.play.tick(0), TimerMode::Repeating),
+            },
+        ));
+    }
 }
 
-fn human_update(
+fn play_update(
+    time: Res<Time>,
     keyboard_input: Res<ButtonInput<KeyCode>>,
-    mut commands: Commands,
-    assets: Res<GlobalAssets>,
-    mut scene_query: Query<(&mut Scene, &HumanController)>,
-    mut snake_head_query: Query<
-        &mut Transform,
-        (
-            With<SnakeHeadMarker>,
-            Without<AppleMarker>,
-            Without<ColliderMarker>,
-        ),
-    >,
-    mut apple_query: Query<
-        &mut Transform,
-        (
-            With<AppleMarker>,
-            Without<SnakeHeadMarker>,
-            Without<ColliderMarker>,
-        ),
-    >,
-    mut collider_query: Query<&mut Transform, With<ColliderMarker>>,
+    gamepad_input: Res<ButtonInput<GamepadButton>>,
+    config: Res<Config>,
+    mut scene_query: Query<(&mut Scene, &mut SceneSimulation, &mut PlayController)>,
+    mut view: SceneView,
 ) {
-    let (mut scene, controller) = scene_query.single_mut();
-    if let Some(pressed_orientation) = controller.orientation_pressed(&keyboard_input) {
-        let mut snake_head_transform = snake_head_query.get_mut(scene.snake_head.ge.id).unwrap();
-        let mut apple_transform = apple_query.get_mut(scene.apple.ge.id).unwrap();
-
-        if let Some(action) = scene
-            .snake_head
-            .orientation
-            .apply_to_action(&pressed_orientation)
-        {
-            match scene.play_step(
-                &mut commands,
-                &assets,
-                &mut collider_query,
-                &mut snake_head_transform,
-                &mut apple_transform,
-                action,
-            ) {
-                PlayerStepResult::Nothing => {}
-                PlayerStepResult::AppleEaten => {
-                    println!("Apple eaten! Punctuation: {:?}", scene.punctuation)
-                }
-                PlayerStepResult::Collision => {
-                    println!("Collision! Game reset");
-                    scene.reset(
-                        &mut commands,
-                        &assets,
-                        &mut snake_head_transform,
-                        &mut apple_transform,
-                    );
-                }
+    let mut boards: Vec<_> = scene_query.iter_mut().collect();
+    boards.sort_by_key(|(_, _, controller)| controller.board);
+    let mut ticks = Vec::with_capacity(boards.len());
+    for (_, sim, controller) in boards.iter_mut() {
+        controller.read_input(&keyboard_input, &gamepad_input, &sim.0);
+        ticks.push(controller.tick(&time, &config.play, &sim.0));
+    }
+
+    if !config.play.lockstep {
+        for ((scene, sim, controller), ticked) in boards.iter_mut().zip(ticks) {
+            if !ticked {
+                continue;
             }
+            let sim = &mut sim.0;
+            let actions = controller.actions(sim);
+            play_step(controller.board, sim, &actions);
+            if sim.is_over() {
+                controller.reset(sim);
+            }
+            scene.sync(sim, &mut view);
         }
+        return;
+    }
+
+    // The boards of the living humans lead the pace of every board, else any timer does.
+    let leaders: Vec<bool> = boards
+        .iter()
+        .map(|(_, sim, controller)| controller.humans_alive(&sim.0))
+        .collect();
+    let due = if leaders.contains(&true) {
+        ticks
+            .iter()
+            .zip(&leaders)
+            .any(|(ticked, leads)| *ticked && *leads)
+    } else {
+        ticks.contains(&true)
+    };
+    if !due {
+        return;
+    }
+    for (_, sim, controller) in boards.iter_mut() {
+        if !sim.0.is_over() {
+            let actions = controller.actions(&sim.0);
+            play_step(controller.board, &mut sim.0, &actions);
+        }
+    }
+
+    let humans_play = boards
+        .iter()
+        .any(|(_, _, controller)| controller.has_humans());
+    let humans_alive = boards
+        .iter()
+        .any(|(_, sim, controller)| controller.humans_alive(&sim.0));
+    let round_over = if humans_play {
+        !humans_alive
+    } else {
+        boards.iter().all(|(_, sim, _)| sim.0.is_over())
+    };
+    for (scene, sim, controller) in boards.iter_mut() {
+        if round_over {
+            controller.reset(&mut sim.0);
+        }
+        scene.sync(&sim.0, &mut view);
     }
 }
 
-fn init_ai(mut commands: Commands, assets: Res<GlobalAssets>) {
-    commands.spawn(Camera2dBundle::default());
+/// Steps a played board, printing what happened to each snake.
+fn play_step(board: usize, sim: &mut Simulation, actions: &[PlayerStepAction]) {
+    let results = sim.step_all(actions);
+    for (index, result) in results.into_iter().enumerate() {
+        let snake = sim.snake(index);
+        match result {
+            PlayerStepResult::Nothing => {}
+            PlayerStepResult::AppleEaten => println!(
+                "Board {}, snake {}: apple eaten! Punctuation: {:?}",
+                board, index, snake.punctuation
+            ),
+            PlayerStepResult::GoldenAppleEaten => println!(
+                "Board {}, snake {}: golden apple eaten! Punctuation: {:?}",
+                board, index, snake.punctuation
+            ),
+            PlayerStepResult::PoisonEaten { fatal: false } => println!(
+                "Board {}, snake {}: poison eaten! Length: {:?}",
+                board,
+                index,
+                snake.len()
+            ),
+            PlayerStepResult::PoisonEaten { fatal: true } => {
+                println!("Board {}, snake {}: deadly poison eaten!", board, index)
+            }
+            PlayerStepResult::Collision => {
+                println!("Board {}, snake {}: collision!", board, index)
+            }
+            PlayerStepResult::Won => println!("Board {}, snake {}: board full, won!", board, index),
+        }
+    }
+}
 
-    commands.spawn(AiController {
-        plot_scores: Vec::new(),
-        plot_mean_scores: Vec::new(),
-        total_score: 0,
-        record: 0,
-        agent: Mutex::new(Agent::load_if_exists("model.ot")),
-    });
+fn print_game_over(board: usize, sim: &Simulation) {
+    let scores: Vec<String> = sim
+        .snakes()
+        .iter()
+        .map(|snake| snake.punctuation.to_string())
+        .collect();
+    println!("Board {}: game over! Scores: {}", board, scores.join(" | "));
+}
 
-    let margin = 1.1;
-    let x_count = 5;
-    let y_count = 3;
+fn init_ai(
+    mut commands: Commands,
+    assets: Res<GlobalAssets>,
+    settings: Res<RunSettings>,
+    config: Res<Config>,
+) {
+    let seed = settings.seed.unwrap();
+    let trainer = if config.selfplay.enabled {
+        Trainer::SelfPlay(Box::new(SelfPlaySession::new(&settings, &config)))
+    } else {
+        let envs = VecEnv::new(
+            (0..settings.scenes as u64)
+                .map(|index| SnakeGame::new(derive_seed(seed, index), &config))
+                .collect(),
+        );
+        Trainer::Single {
+            session: Box::new(TrainingSession::new(&settings, &config, &envs.envs()[0])),
+            envs,
+        }
+    };
+    let arena = *trainer.sim(0).arena();
 
-    let mut x_index = -x_count as f32 / 2.0 - 0.5;
+    let margin = 1.1;
+    // Keeps the 5:3 layout of the default 15 scenes for any count.
+    let x_count = ((settings.scenes as f32 * 5.0 / 3.0).sqrt().ceil() as usize).max(1);
+    let y_count = settings.scenes.div_ceil(x_count);
+    spawn_camera(
+        &mut commands,
+        &arena,
+        Vec2::new(x_count as f32, y_count as f32),
+        margin,
+    );
+
+    let mut scene_index = 0;
+
+    let mut x_index = -(x_count as f32) / 2.0 - 0.5;
     for _ in 0..x_count {
         x_index += 1.0;
 
-        let mut y_index = -y_count as f32 / 2.0 - 0.5;
+        let mut y_index = -(y_count as f32) / 2.0 - 0.5;
         for _ in 0..y_count {
             y_index += 1.0;
 
+            if scene_index == settings.scenes {
+                break;
+            }
+
             let scene_id = init_scene(
                 &mut commands,
                 &assets,
                 Transform::from_xyz(
-                    RECT_SIZE * ARENA.width() as f32 * x_index * margin,
-                    RECT_SIZE * ARENA.height() as f32 * y_index * margin,
+                    RECT_SIZE * (arena.width() + 2) as f32 * x_index * margin,
+                    RECT_SIZE * (arena.height() + 2) as f32 * y_index * margin,
                     0.0,
                 ),
+                trainer.sim(scene_index),
             );
-            commands.entity(scene_id).insert(AiControllerDependent);
+            commands
+                .entity(scene_id)
+                .insert(AiControllerDependent(scene_index));
+            scene_index += 1;
         }
     }
+
+    commands.spawn(AiController {
+        plot_scores: Vec::new(),
+        plot_mean_scores: Vec::new(),
+        trainer: Mutex::new(trainer),
+    });
 }
 
 fn ai_update(
-    mut commands: Commands,
     mut ctx: EguiContexts,
-    assets: Res<GlobalAssets>,
     mut controller_query: Query<&mut AiController>,
-    mut scene_query: Query<&mut Scene, With<AiControllerDependent>>,
-    mut snake_head_query: Query<
-        &mut Transform,
-        (
-            With<SnakeHeadMarker>,
-            Without<AppleMarker>,
-            Without<ColliderMarker>,
-        ),
-    >,
-    mut apple_query: Query<
-        &mut Transform,
-        (
-            With<AppleMarker>,
-            Without<SnakeHeadMarker>,
-            Without<ColliderMarker>,
-        ),
-    >,
-    mut collider_query: Query<&mut Transform, With<ColliderMarker>>,
+    mut scene_query: Query<(&mut Scene, &AiControllerDependent)>,
+    mut view: SceneView,
 ) {
     let mut controller = controller_query.single_mut();
-    let mut agent = controller.agent.lock().unwrap();
-
-    let mut done_scores = Vec::new();
-    let mut best_score = 0;
-
-    let scene_query = scene_query.iter_mut();
-    let snapshots_stored = scene_query.len();
-    for mut scene in scene_query {
-        let mut snake_head_transform = snake_head_query.get_mut(scene.snake_head.ge.id).unwrap();
-        let mut apple_transform = apple_query.get_mut(scene.apple.ge.id).unwrap();
+    let controller = &mut *controller;
 
-        let state_old = Agent::get_state(&scene);
+    let trainer = controller.trainer.get_mut().unwrap();
+    let finished = trainer.step();
 
-        let final_move = agent.get_action(&state_old);
-
-        let (reward, done, score) = match scene.play_step(
-            &mut commands,
-            &assets,
-            &mut collider_query,
-            &mut snake_head_transform,
-            &mut apple_transform,
-            AiController::action(&final_move),
-        ) {
-            PlayerStepResult::Nothing => {
-                if scene.frame_iteration > 100 * scene.snake_len() {
-                    (-10.0, true, scene.punctuation)
-                } else {
-                    (0.0, false, scene.punctuation)
-                }
-            }
-            PlayerStepResult::AppleEaten => (10.0, false, scene.punctuation),
-            PlayerStepResult::Collision => {
-                scene.frame_iteration += 1;
-                (-10.0, true, scene.punctuation)
-            }
-        };
-
-        let state_new = Agent::get_state(&scene);
-
-        let snapshot = Snapshot {
-            state: state_old,
-            action: final_move,
-            reward,
-            next_state: state_new,
-            done,
-        };
-
-        agent.remember(snapshot);
-
-        if done {
-            scene.reset(
-                &mut commands,
-                &assets,
-                &mut snake_head_transform,
-                &mut apple_transform,
-            );
-
-            done_scores.push(score);
-            if score > best_score {
-                best_score = score;
-            }
-        }
+    for (mut scene, dependent) in scene_query.iter_mut() {
+        scene.sync(trainer.sim(dependent.0), &mut view);
     }
 
-    agent.train_with_last(snapshots_stored);
-
-    let old_n_games = agent.n_games;
-    agent.n_games += done_scores.len();
-
-    if best_score != 0 {
-        agent.train_long_memory();
-
-        if best_score > controller.record {
-            agent.save("model.ot").unwrap();
-        }
-    }
-    drop(agent);
-
-    if best_score > controller.record {
-        controller.record = best_score;
-    }
-
-    for (i, score) in done_scores.into_iter().enumerate() {
-        let game_number = (old_n_games + i + 1) as f64;
-
-        controller.total_score += score;
-        let mean_score = controller.total_score as f64 / game_number;
-
-        controller.plot_scores.push([game_number, score as f64]);
-        controller.plot_mean_scores.push([game_number, mean_score]);
+    for game in finished {
+        let game_number = game.number as f64;
+        controller
+            .plot_scores
+            .push([game_number, game.score as f64]);
+        controller
+            .plot_mean_scores
+            .push([game_number, game.mean_score]);
     }
 
     egui::CentralPanel::default()
@@ -409,3 +662,40 @@ fn ai_update(
                 });
         });
 }
+
+fn init_replay(mut commands: Commands, assets: Res<GlobalAssets>, replay: Res<WatchedReplay>) {
+    spawn_camera(&mut commands, &replay.0.level.arena, Vec2::ONE, 1.1);
+
+    let sim = Simulation::new(replay.0.seed, replay.0.level.clone());
+    let scene_id = init_scene(&mut commands, &assets, Transform::default(), &sim);
+    commands.entity(scene_id).insert((
+        SceneSimulation(sim),
+        ReplayController {
+            cursor: 0,
+            timer: Timer::from_seconds(0.05, TimerMode::Repeating),
+        },
+    ));
+}
+
+fn replay_update(
+    time: Res<Time>,
+    replay: Res<WatchedReplay>,
+    mut scene_query: Query<(&mut Scene, &mut SceneSimulation, &mut ReplayController)>,
+    mut view: SceneView,
+) {
+    let (mut scene, mut sim, mut controller) = scene_query.single_mut();
+    if !controller.timer.tick(time.delta()).just_finished() {
+        return;
+    }
+
+    if let Some(action) = replay.0.actions.get(controller.cursor) {
+        let actions = with_scripted_opponents(&sim.0, *action);
+        sim.0.step_all(&actions);
+        controller.cursor += 1;
+    } else {
+        sim.0 = Simulation::new(replay.0.seed, replay.0.level.clone());
+        controller.cursor = 0;
+    }
+
+    scene.sync(&sim.0, &mut view);
+}
@@ -1,17 +1,16 @@
-use tch::nn::{self, Adam, Linear, Module, Optimizer, OptimizerConfig, VarStore};
+use tch::nn::{self, Adam, Conv2D, Linear, Module, Optimizer, OptimizerConfig, VarStore};
 use tch::{IndexOp, Tensor};
 
 use crate::DType;
 
-pub const STATE_SIZE: usize = 11;
 pub const ACTION_SIZE: usize = 3;
 pub type REWARD = DType;
 
 pub struct Snapshot {
-    pub state: [DType; STATE_SIZE],
+    pub state: Vec<DType>,
     pub action: [DType; ACTION_SIZE],
     pub reward: REWARD,
-    pub next_state: [DType; STATE_SIZE],
+    pub next_state: Vec<DType>,
     pub done: bool,
 }
 pub struct SnapshotConcat {
@@ -21,16 +20,19 @@ pub struct SnapshotConcat {
     pub next_state: Vec<DType>,
     pub done: Vec<bool>,
     target_size: usize,
+    state_size: usize,
 }
 impl SnapshotConcat {
-    pub fn building(target_size: usize) -> Self {
+    /// Room for `target_size` snapshots whose observations flatten to `state_size` values.
+    pub fn building(target_size: usize, state_size: usize) -> Self {
         Self {
-            state: Vec::with_capacity(target_size * STATE_SIZE),
+            state: Vec::with_capacity(target_size * state_size),
             action: Vec::with_capacity(target_size * ACTION_SIZE),
             reward: Vec::with_capacity(target_size),
-            next_state: Vec::with_capacity(target_size * STATE_SIZE),
+            next_state: Vec::with_capacity(target_size * state_size),
             done: Vec::with_capacity(target_size),
             target_size,
+            state_size,
         }
     }
 
@@ -38,6 +40,11 @@ impl SnapshotConcat {
         if self.is_built() {
             panic!("SnapshotConcat already built (full).");
         }
+        assert_eq!(
+            snapshot.state.len(),
+            self.state_size,
+            "Snapshot state of another observation shape."
+        );
         self.state.extend_from_slice(&snapshot.state);
         self.action.extend_from_slice(&snapshot.action);
         self.reward.push(snapshot.reward);
@@ -69,14 +76,107 @@ impl Module for LinerQNet {
     }
 }
 
+/// Convolutions over `[channels, height, width]` planes, fed flattened like the linear model: a
+/// single observation, or a batch of them as rows.
+#[derive(Debug)]
+pub struct ConvQNet {
+    conv1: Conv2D,
+    conv2: Conv2D,
+    fc1: Linear,
+    fc2: Linear,
+    shape: [i64; 3],
+}
+impl ConvQNet {
+    pub fn new(vs: &VarStore, shape: [i64; 3], hidden_size: i64, output_size: i64) -> Self {
+        let [channels, height, width] = shape;
+        let same = nn::ConvConfig {
+            padding: 1,
+            ..Default::default()
+        };
+        let halving = nn::ConvConfig { stride: 2, ..same };
+        let conv1 = nn::conv2d(vs.root(), channels, 16, 3, same);
+        let conv2 = nn::conv2d(vs.root(), 16, 32, 3, halving);
+        let flat_size = 32 * ((height - 1) / 2 + 1) * ((width - 1) / 2 + 1);
+        let fc1 = nn::linear(vs.root(), flat_size, hidden_size, Default::default());
+        let fc2 = nn::linear(vs.root(), hidden_size, output_size, Default::default());
+
+        Self {
+            conv1,
+            conv2,
+            fc1,
+            fc2,
+            shape,
+        }
+    }
+}
+impl Module for ConvQNet {
+    fn forward(&self, xs: &Tensor) -> Tensor {
+        let [channels, height, width] = self.shape;
+        let q = xs
+            .view([-1, channels, height, width])
+            .apply(&self.conv1)
+            .relu()
+            .apply(&self.conv2)
+            .relu()
+            .flatten(1, -1)
+            .apply(&self.fc1)
+            .relu()
+            .apply(&self.fc2);
+        if xs.dim() == 1 {
+            q.view([-1])
+        } else {
+            q
+        }
+    }
+}
+
+/// The model fitting the shape of the observations: linear over flat features, convolutional
+/// over planes.
+#[derive(Debug)]
+pub enum QNet {
+    Linear(LinerQNet),
+    Conv(Box<ConvQNet>),
+}
+impl QNet {
+    pub fn new(
+        vs: &VarStore,
+        observation_shape: &[usize],
+        hidden_size: i64,
+        output_size: i64,
+    ) -> Self {
+        match *observation_shape {
+            [channels, height, width] => QNet::Conv(Box::new(ConvQNet::new(
+                vs,
+                [channels as i64, height as i64, width as i64],
+                hidden_size,
+                output_size,
+            ))),
+            _ => QNet::Linear(LinerQNet::new(
+                vs,
+                observation_shape.iter().product::<usize>() as i64,
+                hidden_size,
+                output_size,
+            )),
+        }
+    }
+}
+impl Module for QNet {
+    fn forward(&self, xs: &Tensor) -> Tensor {
+        match self {
+            QNet::Linear(model) => model.forward(xs),
+            QNet::Conv(model) => model.forward(xs),
+        }
+    }
+}
+
 pub struct QTrainer {
-    pub model: LinerQNet,
+    pub model: QNet,
     gamma: f32,
     optimizer: Optimizer,
 }
 
 impl QTrainer {
-    pub fn new(vs: &VarStore, model: LinerQNet, lr: f64, gamma: f32) -> Self {
+    pub fn new(vs: &VarStore, model: QNet, lr: f64, gamma: f32) -> Self {
         let optimizer = Adam::default().build(&vs, lr).unwrap();
         Self {
             model,
@@ -131,8 +231,8 @@ impl QTrainer {
             );
         }
         let len = snapshots.target_size as i64;
-        let state_view = [len, STATE_SIZE as i64];
-        let next_state_view = [len, STATE_SIZE as i64];
+        let state_view = [len, snapshots.state_size as i64];
+        let next_state_view = [len, snapshots.state_size as i64];
         let action_view = [len, ACTION_SIZE as i64];
         let reward_view = [len, 1];
 
@@ -146,7 +246,7 @@ impl QTrainer {
     }
 
     pub fn train_single_step(&mut self, snapshot: &Snapshot) {
-        let mut snapshots = SnapshotConcat::building(1);
+        let mut snapshots = SnapshotConcat::building(1, snapshot.state.len());
         snapshots.push(snapshot);
         self.train_multiple_steps(snapshots);
     }
@@ -0,0 +1,584 @@
+use std::collections::HashMap;
+
+use crate::{
+    config::EnvConfig,
+    env::Observation,
+    simulation::{
+        Arena, ColliderVariant, FoodKind, GridPos, PlayerStepAction, Simulation, SnakeOrientation,
+    },
+    DType,
+};
+
+/// Values of the [`Features`] observation.
+pub const FEATURES_SIZE: usize = 11;
+
+/// Planes of the [`Grid`] observation: walls and obstacles, own body, own head, other snakes,
+/// edible apples and poison apples.
+pub const GRID_CHANNELS: usize = 6;
+
+/// Planes of the [`Window`] observation: blocked cells, own body, edible apples and poison
+/// apples.
+pub const WINDOW_CHANNELS: usize = 4;
+
+/// Values of the [`ReachableSpace`] observation: for the forward, left and right moves, the
+/// fraction of the free cells still reachable from the head afterwards, and whether the tail is.
+pub const REACHABLE_SIZE: usize = 6;
+
+/// Directions of the [`Rays`] observation, as `(right, forward)` steps relative to the head,
+/// clockwise from straight ahead.
+const RAYS: [(i32, i32); 8] = [
+    (0, 1),
+    (1, 1),
+    (1, 0),
+    (1, -1),
+    (0, -1),
+    (-1, -1),
+    (-1, 0),
+    (-1, 1),
+];
+
+/// Something a snake sees of the board.
+pub trait ObservationEncoder: Send + Sync {
+    /// Shape of the observations on `arena`: `[size]` for values, `[channels, height, width]`
+    /// for planes.
+    fn shape(&self, arena: &Arena) -> Vec<usize>;
+
+    /// Lowest value of the observations, the highest being 1.
+    fn low(&self) -> DType {
+        0.0
+    }
+
+    /// Observation of the snake at `index`, flattened.
+    fn encode(&self, sim: &Simulation, index: usize) -> Observation;
+}
+
+/// The dangers around the head, its direction and the direction of the nearest apple.
+pub struct Features;
+impl ObservationEncoder for Features {
+    fn shape(&self, _arena: &Arena) -> Vec<usize> {
+        vec![FEATURES_SIZE]
+    }
+
+    fn encode(&self, sim: &Simulation, index: usize) -> Observation {
+        features(sim, index)
+    }
+}
+
+/// The whole board, ring of walls included, as `[GRID_CHANNELS, height + 2, width + 2]` planes.
+/// Bodies are aged from the tail, near 0, to the part behind the head, near 1.
+pub struct Grid;
+impl ObservationEncoder for Grid {
+    fn shape(&self, arena: &Arena) -> Vec<usize> {
+        vec![
+            GRID_CHANNELS,
+            arena.height() as usize + 2,
+            arena.width() as usize + 2,
+        ]
+    }
+
+    fn encode(&self, sim: &Simulation, index: usize) -> Observation {
+        grid(sim, index)
+    }
+}
+
+/// The `size` square around the head, turned so that the snake heads up, as `WINDOW_CHANNELS`
+/// flattened planes, followed by the direction of the nearest apple as `(right, forward)`. Its
+/// size does not depend on the arena.
+pub struct Window {
+    /// Odd, so that the head is centered.
+    pub size: usize,
+}
+impl ObservationEncoder for Window {
+    fn shape(&self, _arena: &Arena) -> Vec<usize> {
+        vec![WINDOW_CHANNELS * self.size * self.size + 2]
+    }
+
+    fn low(&self) -> DType {
+        -1.0
+    }
+
+    fn encode(&self, sim: &Simulation, index: usize) -> Observation {
+        window(sim, index, self.size)
+    }
+}
+
+/// For each of 8 rays cast from the head, clockwise from straight ahead, the inverse distance to
+/// the first wall, to the first part of its own body and to the first edible apple, 0 when the
+/// ray meets none. Obstacles and other snakes count as walls.
+pub struct Rays;
+impl ObservationEncoder for Rays {
+    fn shape(&self, _arena: &Arena) -> Vec<usize> {
+        vec![RAYS.len() * 3]
+    }
+
+    fn encode(&self, sim: &Simulation, index: usize) -> Observation {
+        rays(sim, index)
+    }
+}
+
+/// For the forward, left and right moves, the fraction of the free cells left reachable by a
+/// flood fill from the head, and whether the tail is.
+pub struct ReachableSpace;
+impl ObservationEncoder for ReachableSpace {
+    fn shape(&self, _arena: &Arena) -> Vec<usize> {
+        vec![REACHABLE_SIZE]
+    }
+
+    fn encode(&self, sim: &Simulation, index: usize) -> Observation {
+        reachable_space(sim, index).to_vec()
+    }
+}
+
+type EncoderFactory = fn(&EnvConfig) -> Result<Box<dyn ObservationEncoder>, String>;
+
+/// Encoders selectable by name in `env.observation`.
+const REGISTRY: [(&str, EncoderFactory); 5] = [
+    ("features", |_| Ok(Box::new(Features))),
+    ("grid", |_| Ok(Box::new(Grid))),
+    ("window", |config| {
+        if config.window_size % 2 == 0 {
+            return Err(format!(
+                "the window must have an odd size, got {}",
+                config.window_size
+            ));
+        }
+        Ok(Box::new(Window {
+            size: config.window_size,
+        }))
+    }),
+    ("rays", |_| Ok(Box::new(Rays))),
+    ("reachable", |_| Ok(Box::new(ReachableSpace))),
+];
+
+/// Most values next to planes, each of them becoming a whole plane.
+pub const MAX_VALUE_PLANES: usize = 32;
+
+/// Encoders seen together, in order. Values are concatenated, unless one of the encoders gives
+/// planes: the planes are then stacked and every value becomes a constant plane after them, so
+/// at most [`MAX_VALUE_PLANES`] values go with planes.
+pub struct Encoder {
+    parts: Vec<Box<dyn ObservationEncoder>>,
+}
+impl Encoder {
+    /// The registered encoders called `names`, with their settings taken from `config`.
+    pub fn from_names(names: &[String], config: &EnvConfig) -> Result<Self, String> {
+        if names.is_empty() {
+            return Err("no observation encoder".to_owned());
+        }
+        let parts = names
+            .iter()
+            .map(|name| {
+                let (_, factory) = REGISTRY
+                    .iter()
+                    .find(|(registered, _)| registered == name)
+                    .ok_or_else(|| {
+                        let known: Vec<_> = REGISTRY.iter().map(|(name, _)| *name).collect();
+                        format!(
+                            "unknown observation {:?}, expected one of {:?}",
+                            name, known
+                        )
+                    })?;
+                factory(config)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let arena = config.arena();
+        let (planes, values): (Vec<_>, Vec<_>) = parts
+            .iter()
+            .map(|part| part.shape(&arena))
+            .partition(|shape| shape.len() == 3);
+        let value_count: usize = values.iter().flatten().sum();
+        if !planes.is_empty() && value_count > MAX_VALUE_PLANES {
+            return Err(format!(
+                "{} values would each become a plane next to the planes, at most {} can",
+                value_count, MAX_VALUE_PLANES
+            ));
+        }
+        Ok(Self { parts })
+    }
+
+    /// Height and width of the planes of the parts, if any gives planes.
+    fn plane(&self, arena: &Arena) -> Option<(usize, usize)> {
+        let mut planes = self
+            .parts
+            .iter()
+            .map(|part| part.shape(arena))
+            .filter(|shape| shape.len() == 3)
+            .map(|shape| (shape[1], shape[2]));
+        let plane = planes.next()?;
+        if let Some(other) = planes.find(|other| *other != plane) {
+            panic!("Cannot stack planes of sizes {:?} and {:?}.", plane, other);
+        }
+        Some(plane)
+    }
+}
+impl ObservationEncoder for Encoder {
+    fn shape(&self, arena: &Arena) -> Vec<usize> {
+        let shapes = self.parts.iter().map(|part| part.shape(arena));
+        match self.plane(arena) {
+            Some((height, width)) => {
+                let channels = shapes
+                    .map(|shape| match shape[..] {
+                        [channels, _, _] => channels,
+                        _ => shape.iter().product(),
+                    })
+                    .sum();
+                vec![channels, height, width]
+            }
+            None => vec![shapes.map(|shape| shape.iter().product::<usize>()).sum()],
+        }
+    }
+
+    fn low(&self) -> DType {
+        self.parts
+            .iter()
+            .map(|part| part.low())
+            .fold(0.0, DType::min)
+    }
+
+    fn encode(&self, sim: &Simulation, index: usize) -> Observation {
+        let plane = self
+            .plane(sim.arena())
+            .map_or(1, |(height, width)| height * width);
+        let mut observation = Vec::new();
+        for part in &self.parts {
+            let encoded = part.encode(sim, index);
+            if part.shape(sim.arena()).len() == 3 {
+                observation.extend(encoded);
+            } else {
+                for value in encoded {
+                    observation.extend(std::iter::repeat_n(value, plane));
+                }
+            }
+        }
+        observation
+    }
+}
+
+fn features(sim: &Simulation, index: usize) -> Observation {
+    let snake = sim.snake(index);
+    let head_pos = &snake.head.pos;
+    // Once the snakes fill the arena there is no food left, nor any direction to it.
+    let food_pos = &sim
+        .nearest_apple(index)
+        .map_or(*head_pos, |apple| apple.pos);
+
+    let arena = sim.arena();
+    let (food_x, food_y) = arena.offset(head_pos, food_pos);
+    let point_l = arena.wrap_pos(GridPos::new(head_pos.x - 1, head_pos.y));
+    let point_r = arena.wrap_pos(GridPos::new(head_pos.x + 1, head_pos.y));
+    let point_u = arena.wrap_pos(GridPos::new(head_pos.x, head_pos.y - 1));
+    let point_d = arena.wrap_pos(GridPos::new(head_pos.x, head_pos.y + 1));
+
+    let head_direction = &snake.head.orientation;
+    let dir_l = head_direction == &SnakeOrientation::Left;
+    let dir_r = head_direction == &SnakeOrientation::Right;
+    let dir_u = head_direction == &SnakeOrientation::Up;
+    let dir_d = head_direction == &SnakeOrientation::Down;
+
+    vec![
+        // Danger straight
+        ((dir_r & sim.is_blocked(&point_r))
+            | (dir_l & sim.is_blocked(&point_l))
+            | (dir_u & sim.is_blocked(&point_u))
+            | (dir_d & sim.is_blocked(&point_d))) as u8 as DType,
+        // Danger right
+        ((dir_u & sim.is_blocked(&point_r))
+            | (dir_d & sim.is_blocked(&point_l))
+            | (dir_l & sim.is_blocked(&point_u))
+            | (dir_r & sim.is_blocked(&point_d))) as u8 as DType,
+        // Danger left
+        ((dir_d & sim.is_blocked(&point_r))
+            | (dir_u & sim.is_blocked(&point_l))
+            | (dir_r & sim.is_blocked(&point_u))
+            | (dir_l & sim.is_blocked(&point_d))) as u8 as DType,
+        // Move head_direction
+        dir_l as u8 as DType,
+        dir_r as u8 as DType,
+        dir_u as u8 as DType,
+        dir_d as u8 as DType,
+        // Food location
+        (food_x < 0) as u8 as DType, // food left
+        (food_x > 0) as u8 as DType, // food right
+        (food_y < 0) as u8 as DType, // food up
+        (food_y > 0) as u8 as DType, // food down
+    ]
+}
+
+fn grid(sim: &Simulation, index: usize) -> Observation {
+    let arena = sim.arena();
+    let width = arena.width() as usize + 2;
+    let height = arena.height() as usize + 2;
+    let mut planes = vec![0.0; GRID_CHANNELS * height * width];
+    let mut set = |channel: usize, pos: &GridPos, value: DType| {
+        let x = pos.x - arena.min.x + 1;
+        let y = pos.y - arena.min.y + 1;
+        if (0..width as i32).contains(&x) && (0..height as i32).contains(&y) {
+            planes[(channel * height + y as usize) * width + x as usize] = value;
+        }
+    };
+
+    for pos in sim.walls().iter().chain(sim.obstacles()) {
+        set(0, pos, 1.0);
+    }
+    for (i, snake) in sim.snakes().iter().enumerate() {
+        let own = i == index;
+        if !own && !snake.alive {
+            continue;
+        }
+        let (body, head) = if own { (1, 2) } else { (3, 3) };
+        let len = snake.len() as DType;
+        for (age, pos) in snake.body_parts().iter().enumerate() {
+            set(body, pos, (age + 1) as DType / len);
+        }
+        set(head, &snake.head.pos, 1.0);
+    }
+    for apple in sim.apples() {
+        let channel = match apple.kind {
+            FoodKind::Regular | FoodKind::Golden => 4,
+            FoodKind::Poison => 5,
+        };
+        set(channel, &apple.pos, 1.0);
+    }
+
+    planes
+}
+
+/// Unit steps ahead of and to the right of a snake heading towards `orientation`.
+fn axes(orientation: &SnakeOrientation) -> ((i32, i32), (i32, i32)) {
+    let unit = |orientation: &SnakeOrientation| {
+        let pos = orientation.next(&GridPos::new(0, 0));
+        (pos.x, pos.y)
+    };
+    (
+        unit(orientation),
+        unit(&PlayerStepAction::Right.rotate(orientation)),
+    )
+}
+
+/// Whether `pos` is out of the arena, a wall, an obstacle or a part of another living snake,
+/// as seen by the snake at `index`.
+fn blocks(sim: &Simulation, index: usize, pos: &GridPos) -> bool {
+    match sim.collider(pos) {
+        _ if !sim.arena().contains(pos) => true,
+        Some(ColliderVariant::Wall) => true,
+        Some(ColliderVariant::SnakeBody(owner)) => *owner != index,
+        None => sim
+            .snakes()
+            .iter()
+            .enumerate()
+            .any(|(i, other)| i != index && other.alive && other.head.pos == *pos),
+    }
+}
+
+fn window(sim: &Simulation, index: usize, size: usize) -> Observation {
+    let snake = sim.snake(index);
+    let head = snake.head.pos;
+    let (forward, right) = axes(&snake.head.orientation);
+
+    let arena = sim.arena();
+    let len = snake.len() as DType;
+    let ages: HashMap<GridPos, DType> = snake
+        .body_parts()
+        .iter()
+        .enumerate()
+        .map(|(age, pos)| (*pos, (age + 1) as DType / len))
+        .collect();
+
+    let plane = size * size;
+    let half = (size / 2) as i32;
+    let mut observation = vec![0.0; WINDOW_CHANNELS * plane + 2];
+    // Row 0 is the farthest ahead, column 0 the farthest to the left.
+    for row in 0..size {
+        for column in 0..size {
+            let ahead = half - row as i32;
+            let aside = column as i32 - half;
+            let pos = arena.wrap_pos(GridPos::new(
+                head.x + ahead * forward.0 + aside * right.0,
+                head.y + ahead * forward.1 + aside * right.1,
+            ));
+            let cell = row * size + column;
+
+            if blocks(sim, index, &pos) {
+                observation[cell] = 1.0;
+            }
+            if let Some(age) = ages.get(&pos) {
+                observation[plane + cell] = *age;
+            }
+            if let Some(apple) = sim.apples().iter().find(|apple| apple.pos == pos) {
+                let channel = match apple.kind {
+                    FoodKind::Regular | FoodKind::Golden => 2,
+                    FoodKind::Poison => 3,
+                };
+                observation[channel * plane + cell] = 1.0;
+            }
+        }
+    }
+
+    if let Some(apple) = sim.nearest_apple(index) {
+        let (dx, dy) = arena.offset(&head, &apple.pos);
+        let distance = (dx.abs() + dy.abs()).max(1) as DType;
+        let apple_right = (dx * right.0 + dy * right.1) as DType / distance;
+        let apple_forward = (dx * forward.0 + dy * forward.1) as DType / distance;
+        observation[WINDOW_CHANNELS * plane] = apple_right;
+        observation[WINDOW_CHANNELS * plane + 1] = apple_forward;
+    }
+
+    observation
+}
+
+fn rays(sim: &Simulation, index: usize) -> Observation {
+    let snake = sim.snake(index);
+    let (forward, right) = axes(&snake.head.orientation);
+    let arena = sim.arena();
+    // A ray across wrapping borders may never meet a wall, so it stops after crossing the arena.
+    let range = arena.width().max(arena.height());
+
+    let mut observation = Vec::with_capacity(RAYS.len() * 3);
+    for (aside, ahead) in RAYS {
+        let step = (
+            ahead * forward.0 + aside * right.0,
+            ahead * forward.1 + aside * right.1,
+        );
+        let (mut wall, mut body, mut apple) = (0.0, 0.0, 0.0);
+        let mut pos = snake.head.pos;
+        for distance in 1..=range {
+            pos = arena.wrap_pos(GridPos::new(pos.x + step.0, pos.y + step.1));
+            let inverse = 1.0 / distance as DType;
+            if blocks(sim, index, &pos) {
+                wall = inverse;
+                break;
+            }
+            if body == 0.0 && sim.collider(&pos).is_some() {
+                body = inverse;
+            }
+            if apple == 0.0
+                && sim
+                    .apples()
+                    .iter()
+                    .any(|food| food.pos == pos && food.kind != FoodKind::Poison)
+            {
+                apple = inverse;
+            }
+        }
+        observation.extend([wall, body, apple]);
+    }
+
+    observation
+}
+
+/// Flood fills the arena after each of the forward, left and right moves of the snake at
+/// `index`. Past the first step its own tail is free, as it moves away, while every head
+/// blocks.
+fn reachable_space(sim: &Simulation, index: usize) -> [DType; REACHABLE_SIZE] {
+    let mut values = [0.0; REACHABLE_SIZE];
+    let snake = sim.snake(index);
+    if !snake.alive {
+        return values;
+    }
+
+    let arena = sim.arena();
+    let width = arena.width();
+    let cell = |pos: &GridPos| ((pos.y - arena.min.y) * width + pos.x - arena.min.x) as usize;
+    let tail = snake.body_parts().first().copied();
+    let free = |pos: &GridPos| {
+        arena.contains(pos)
+            && (sim.collider(pos).is_none() || Some(*pos) == tail)
+            && !sim
+                .snakes()
+                .iter()
+                .any(|other| other.alive && other.head.pos == *pos)
+    };
+    let free_count = (arena.min.y..=arena.max.y)
+        .flat_map(|y| (arena.min.x..=arena.max.x).map(move |x| GridPos::new(x, y)))
+        .filter(|pos| free(pos))
+        .count()
+        .max(1);
+
+    let moves = [
+        PlayerStepAction::Forward,
+        PlayerStepAction::Left,
+        PlayerStepAction::Right,
+    ];
+    for (i, action) in moves.iter().enumerate() {
+        let start = arena.next(&snake.head.pos, &action.rotate(&snake.head.orientation));
+        // Moving into any body part kills before the tails move, so the own tail is only free
+        // after the first step.
+        if !free(&start) || sim.is_blocked(&start) {
+            continue;
+        }
+        let mut visited = vec![false; (width * arena.height()) as usize];
+        visited[cell(&start)] = true;
+        let mut pending = vec![start];
+        let mut reached = 0;
+        while let Some(pos) = pending.pop() {
+            reached += 1;
+            for orientation in [
+                SnakeOrientation::Up,
+                SnakeOrientation::Down,
+                SnakeOrientation::Left,
+                SnakeOrientation::Right,
+            ] {
+                let next = arena.next(&pos, &orientation);
+                if free(&next) && !visited[cell(&next)] {
+                    visited[cell(&next)] = true;
+                    pending.push(next);
+                }
+            }
+        }
+        values[i * 2] = reached as DType / free_count as DType;
+        values[i * 2 + 1] = tail.map_or(1.0, |tail| visited[cell(&tail)] as u8 as DType);
+    }
+
+    values
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::level::Level;
+
+    fn encoder(names: &[&str]) -> Result<Encoder, String> {
+        let names: Vec<String> = names.iter().map(|name| name.to_string()).collect();
+        Encoder::from_names(&names, &EnvConfig::default())
+    }
+
+    #[test]
+    fn encoders_concatenate_values_and_stack_planes() {
+        let arena = EnvConfig::default().arena();
+        let flat = encoder(&["features", "rays", "reachable"]).unwrap();
+        assert_eq!(flat.shape(&arena), [FEATURES_SIZE + 24 + REACHABLE_SIZE]);
+
+        let stacked = encoder(&["grid", "reachable"]).unwrap();
+        let shape = stacked.shape(&arena);
+        assert_eq!(shape, [GRID_CHANNELS + REACHABLE_SIZE, 43, 43]);
+        let sim = Simulation::new(0, EnvConfig::default().level());
+        assert_eq!(
+            stacked.encode(&sim, 0).len(),
+            shape.iter().product::<usize>()
+        );
+    }
+
+    #[test]
+    fn encoders_reject_many_values_next_to_planes() {
+        assert!(encoder(&["grid", "window"]).is_err());
+        assert!(encoder(&["window"]).is_ok());
+        assert!(encoder(&["snake"]).is_err());
+        assert!(encoder(&[]).is_err());
+    }
+
+    #[test]
+    fn reachable_space_closes_the_move_into_the_own_tail() {
+        // The snake fills a column of a wrapping arena, so the tail is the cell right ahead.
+        let sim = Simulation::new(0, Level::empty(Arena::new(3, 3, true), 1));
+        let snake = sim.snake(0);
+        let ahead = sim.arena().next(&snake.head.pos, &snake.head.orientation);
+        assert_eq!(snake.body_parts().first(), Some(&ahead));
+
+        // Moving forward collides with the tail before it leaves, nothing is reachable.
+        let values = reachable_space(&sim, 0);
+        assert_eq!(values[..2], [0.0, 0.0]);
+        // Turning, the snake reaches the 6 free cells and its tail.
+        assert_eq!(values[2..], [1.0, 1.0, 1.0, 1.0]);
+    }
+}
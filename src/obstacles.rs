@@ -0,0 +1,241 @@
+use rand::{rngs::StdRng, seq::SliceRandom};
+
+use crate::{
+    level::Level,
+    simulation::{Arena, GridPos, SnakeOrientation},
+};
+
+/// Random walls generated inside a level at the start of every game.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ObstacleSettings {
+    /// Fraction of the free cells of the level turned into obstacles; 0 disables them.
+    pub density: f32,
+    /// Narrowest gap left between two blocked cells of a row or a column.
+    pub min_corridor_width: i32,
+}
+impl Default for ObstacleSettings {
+    fn default() -> Self {
+        Self {
+            density: 0.0,
+            min_corridor_width: 2,
+        }
+    }
+}
+
+/// Cells of the arena, blocked or not. Cells outside a walled arena count as blocked.
+struct Grid {
+    arena: Arena,
+    blocked: Vec<bool>,
+}
+impl Grid {
+    fn index(&self, pos: &GridPos) -> Option<usize> {
+        let pos = self.arena.wrap_pos(*pos);
+        let (x, y) = (pos.x - self.arena.min.x, pos.y - self.arena.min.y);
+        ((0..self.arena.width()).contains(&x) && (0..self.arena.height()).contains(&y))
+            .then_some((y * self.arena.width() + x) as usize)
+    }
+
+    fn is_blocked(&self, pos: &GridPos) -> bool {
+        self.index(pos).is_none_or(|index| self.blocked[index])
+    }
+
+    /// Free cells in a straight line from `pos`, excluded, towards `orientation`.
+    fn free_run(&self, pos: &GridPos, orientation: &SnakeOrientation, max: i32) -> i32 {
+        let mut run = 0;
+        let mut next = self.arena.next(pos, orientation);
+        while run < max && !self.is_blocked(&next) {
+            run += 1;
+            next = self.arena.next(&next, orientation);
+        }
+        run
+    }
+
+    /// Whether the free cells reachable from `start` are all the `free_count` free cells.
+    fn is_connected(&self, start: &GridPos, free_count: usize) -> bool {
+        let mut visited = vec![false; self.blocked.len()];
+        let mut stack = vec![*start];
+        let mut reached = 0;
+        while let Some(pos) = stack.pop() {
+            let Some(index) = self.index(&pos) else {
+                continue;
+            };
+            if self.blocked[index] || visited[index] {
+                continue;
+            }
+            visited[index] = true;
+            reached += 1;
+            for orientation in ORIENTATIONS {
+                stack.push(self.arena.next(&pos, &orientation));
+            }
+        }
+        reached == free_count
+    }
+}
+
+const ORIENTATIONS: [SnakeOrientation; 4] = [
+    SnakeOrientation::Up,
+    SnakeOrientation::Down,
+    SnakeOrientation::Left,
+    SnakeOrientation::Right,
+];
+
+/// Picks the obstacles of one game of `level`. The initial snakes and the three cells in front
+/// of them stay free, no row or column gets a gap narrower than the minimum corridor width, and
+/// every free cell stays reachable from the spawns.
+pub fn generate(level: &Level, rng: &mut StdRng) -> Vec<GridPos> {
+    let settings = &level.obstacles;
+    let arena = level.arena;
+    if settings.density <= 0.0 {
+        return Vec::new();
+    }
+
+    let mut grid = Grid {
+        arena,
+        blocked: vec![false; (arena.width() * arena.height()) as usize],
+    };
+    for wall in &level.walls {
+        if let Some(index) = grid.index(wall) {
+            grid.blocked[index] = true;
+        }
+    }
+
+    let mut protected = level.snake_cells();
+    for spawn in &level.spawns {
+        let mut ahead = spawn.head;
+        for _ in 0..3 {
+            ahead = arena.next(&ahead, &spawn.orientation);
+            protected.push(ahead);
+        }
+    }
+
+    let mut candidates = Vec::new();
+    for y in arena.min.y..=arena.max.y {
+        for x in arena.min.x..=arena.max.x {
+            let pos = GridPos::new(x, y);
+            if !grid.is_blocked(&pos) && !protected.contains(&pos) {
+                candidates.push(pos);
+            }
+        }
+    }
+    let mut free_count = grid.blocked.iter().filter(|blocked| !**blocked).count();
+    let target = (settings.density * candidates.len() as f32).round() as usize;
+    candidates.shuffle(rng);
+
+    let min_width = settings.min_corridor_width;
+    let max_run = arena.width().max(arena.height());
+    let mut obstacles = Vec::new();
+    for pos in candidates {
+        if obstacles.len() == target {
+            break;
+        }
+
+        let narrows_corridor = ORIENTATIONS.iter().any(|orientation| {
+            let run = grid.free_run(&pos, orientation, max_run);
+            run > 0 && run < min_width
+        });
+        if narrows_corridor {
+            continue;
+        }
+
+        let index = grid.index(&pos).unwrap();
+        grid.blocked[index] = true;
+        let free_neighbours = ORIENTATIONS
+            .iter()
+            .filter(|orientation| !grid.is_blocked(&arena.next(&pos, orientation)))
+            .count();
+        // A dead end can be filled without splitting anything.
+        if free_neighbours > 1 && !grid.is_connected(&level.spawns[0].head, free_count - 1) {
+            grid.blocked[index] = false;
+            continue;
+        }
+
+        free_count -= 1;
+        obstacles.push(pos);
+    }
+    obstacles
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use rand::SeedableRng;
+
+    use super::*;
+
+    fn level(min_corridor_width: i32) -> Level {
+        let mut level = Level::empty(Arena::new(15, 15, false), 2);
+        level.obstacles = ObstacleSettings {
+            density: 0.3,
+            min_corridor_width,
+        };
+        level
+    }
+
+    fn inside(arena: &Arena, pos: &GridPos) -> bool {
+        (arena.min.x..=arena.max.x).contains(&pos.x) && (arena.min.y..=arena.max.y).contains(&pos.y)
+    }
+
+    #[test]
+    fn obstacles_keep_every_free_cell_reachable() {
+        for seed in 0..20 {
+            let level = level(1);
+            let obstacles: HashSet<_> = generate(&level, &mut StdRng::seed_from_u64(seed))
+                .into_iter()
+                .collect();
+            assert!(!obstacles.is_empty());
+            assert!(level
+                .snake_cells()
+                .iter()
+                .all(|pos| !obstacles.contains(pos)));
+
+            let arena = level.arena;
+            let free = |pos: &GridPos| inside(&arena, pos) && !obstacles.contains(pos);
+            let free_count = (arena.min.y..=arena.max.y)
+                .flat_map(|y| (arena.min.x..=arena.max.x).map(move |x| GridPos::new(x, y)))
+                .filter(free)
+                .count();
+            let mut reached = HashSet::from([level.spawns[0].head]);
+            let mut pending = vec![level.spawns[0].head];
+            while let Some(pos) = pending.pop() {
+                for orientation in ORIENTATIONS {
+                    let next = orientation.next(&pos);
+                    if free(&next) && reached.insert(next) {
+                        pending.push(next);
+                    }
+                }
+            }
+            assert_eq!(reached.len(), free_count, "seed {}", seed);
+        }
+    }
+
+    #[test]
+    fn obstacles_leave_corridors_of_the_minimum_width() {
+        for seed in 0..20 {
+            let level = level(3);
+            let obstacles: HashSet<_> = generate(&level, &mut StdRng::seed_from_u64(seed))
+                .into_iter()
+                .collect();
+            assert!(!obstacles.is_empty());
+
+            // Free runs between an obstacle and the next blocked cell of its row or column.
+            for pos in &obstacles {
+                for orientation in ORIENTATIONS {
+                    let mut run = 0;
+                    let mut next = orientation.next(pos);
+                    while inside(&level.arena, &next) && !obstacles.contains(&next) {
+                        run += 1;
+                        next = orientation.next(&next);
+                    }
+                    assert!(
+                        run == 0 || run >= 3,
+                        "seed {}: {:?} at {:?}",
+                        seed,
+                        run,
+                        pos
+                    );
+                }
+            }
+        }
+    }
+}
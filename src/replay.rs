@@ -0,0 +1,82 @@
+use std::{fs, io, path::Path};
+
+use crate::{
+    level::Level,
+    simulation::{Arena, PlayerStepAction},
+};
+
+/// A recorded game. The simulation is deterministic for a given seed and level, so these and the
+/// actions are enough to play it again.
+pub struct Replay {
+    pub seed: u64,
+    pub level: Level,
+    pub actions: Vec<PlayerStepAction>,
+}
+
+impl Replay {
+    pub fn new(seed: u64, level: Level) -> Self {
+        Self {
+            seed,
+            level,
+            actions: Vec::new(),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(folder) = path.parent() {
+            fs::create_dir_all(folder)?;
+        }
+
+        let actions: String = self
+            .actions
+            .iter()
+            .map(|action| match action {
+                PlayerStepAction::Forward => 'F',
+                PlayerStepAction::Left => 'L',
+                PlayerStepAction::Right => 'R',
+            })
+            .collect();
+        fs::write(
+            path,
+            format!("seed {}\n{}\n{}", self.seed, actions, self.level),
+        )
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message);
+
+        let content = fs::read_to_string(path)?;
+        let mut lines = content.lines();
+
+        let seed = lines
+            .next()
+            .and_then(|line| line.strip_prefix("seed "))
+            .and_then(|seed| seed.trim().parse().ok())
+            .ok_or_else(|| invalid("missing seed line"))?;
+
+        let actions_line = lines.next().unwrap_or_default();
+        let actions = actions_line
+            .chars()
+            .map(|action| match action {
+                'F' => Ok(PlayerStepAction::Forward),
+                'L' => Ok(PlayerStepAction::Left),
+                'R' => Ok(PlayerStepAction::Right),
+                _ => Err(invalid("unknown action")),
+            })
+            .collect::<io::Result<_>>()?;
+
+        // Replays written before levels existed end with the actions, on the default arena.
+        let level_content: Vec<&str> = lines.collect();
+        let level = if level_content.is_empty() {
+            Level::empty(Arena::default(), 1)
+        } else {
+            Level::parse(&level_content.join("\n"), false)?
+        };
+
+        Ok(Self {
+            seed,
+            level,
+            actions,
+        })
+    }
+}
@@ -0,0 +1,161 @@
+use std::{
+    sync::mpsc::{self, Receiver, SyncSender, TryRecvError},
+    thread,
+    time::Instant,
+};
+
+use tch::Tensor;
+
+use crate::{
+    agent::Agent,
+    cli::RunSettings,
+    config::Config,
+    env::{SnakeEnv, SnakeGame},
+    model::Snapshot,
+    train::{print_progress, TrainingSession},
+    utils::derive_seed,
+    vec_env::VecEnv,
+};
+
+/// Snapshots of one step of every game of a [`VecEnv`], with the scores of the games it
+/// finished.
+pub struct RolloutBatch {
+    pub snapshots: Vec<Snapshot>,
+    pub done_scores: Vec<usize>,
+}
+
+impl RolloutBatch {
+    /// Plays one step on every game of `envs` with the policy of `agent`.
+    pub fn collect<E: SnakeEnv>(agent: &mut Agent, envs: &mut VecEnv<E>) -> Self {
+        let states_old = envs.observations().to_vec();
+        let final_moves = agent.get_actions(&envs.observation_tensor());
+        let actions: Vec<usize> = final_moves.iter().map(Agent::action_index).collect();
+
+        let step = envs.step(&actions);
+
+        let mut snapshots = Vec::with_capacity(envs.len());
+        let mut done_scores = Vec::new();
+        for (i, ((state_old, final_move), next_state)) in states_old
+            .into_iter()
+            .zip(final_moves)
+            .zip(step.final_observations)
+            .enumerate()
+        {
+            snapshots.push(Snapshot {
+                state: state_old,
+                action: final_move,
+                reward: step.rewards[i],
+                next_state,
+                done: step.dones[i],
+            });
+
+            if step.dones[i] {
+                done_scores.push(step.infos[i].score);
+            }
+        }
+
+        Self {
+            snapshots,
+            done_scores,
+        }
+    }
+}
+
+/// Weights of the learner, with the number of games it played so workers explore as much as
+/// it would.
+struct PolicyUpdate {
+    weights: Vec<(String, Tensor)>,
+    n_games: usize,
+}
+
+/// Plays its games with a copy of the learner policy, streaming every step to the learner until
+/// it hangs up.
+fn run_worker(
+    mut agent: Agent,
+    mut envs: VecEnv<SnakeGame>,
+    batches: SyncSender<RolloutBatch>,
+    updates: Receiver<PolicyUpdate>,
+) {
+    loop {
+        match updates.try_recv() {
+            Ok(update) => {
+                agent.load_weights(&update.weights);
+                agent.n_games = update.n_games;
+            }
+            Err(TryRecvError::Empty) => {}
+            Err(TryRecvError::Disconnected) => return,
+        }
+
+        if batches
+            .send(RolloutBatch::collect(&mut agent, &mut envs))
+            .is_err()
+        {
+            return;
+        }
+    }
+}
+
+/// Headless training where `config.rollout.workers` threads play the `settings.scenes` games
+/// and the calling thread only learns. Workers get the learner weights every
+/// `config.rollout.sync_interval` learning steps.
+///
+/// The order in which batches of the workers arrive depends on the scheduler, so unlike the
+/// single threaded training, runs are not reproducible.
+pub fn train_with_workers(settings: RunSettings, config: Config) {
+    let seed = settings.seed.unwrap();
+    let workers = config.rollout.workers;
+    let games_per_worker = settings.scenes.div_ceil(workers).max(1);
+
+    let probe = SnakeGame::new(seed, &config);
+    let mut session = TrainingSession::new(&settings, &config, &probe);
+
+    let (batch_sender, batches) = mpsc::sync_channel(workers * 2);
+    let mut update_senders = Vec::with_capacity(workers);
+    for worker in 0..workers {
+        let envs = VecEnv::new(
+            (0..games_per_worker)
+                .map(|game| {
+                    let index = (worker * games_per_worker + game) as u64;
+                    SnakeGame::new(derive_seed(seed, index), &config)
+                })
+                .collect(),
+        );
+        let mut agent = Agent::new(
+            &config.agent,
+            derive_seed(seed, u64::MAX - worker as u64),
+            &probe,
+        );
+        agent.load_weights(&session.agent.weights());
+        agent.n_games = session.agent.n_games;
+
+        let (update_sender, updates) = mpsc::channel();
+        update_senders.push(update_sender);
+
+        let batch_sender = batch_sender.clone();
+        thread::Builder::new()
+            .name(format!("rollout-{}", worker))
+            .spawn(move || run_worker(agent, envs, batch_sender, updates))
+            .unwrap();
+    }
+    drop(batch_sender);
+
+    let started = Instant::now();
+    let mut steps: usize = 0;
+    for (learn_steps, batch) in (1..).zip(batches) {
+        steps += batch.snapshots.len();
+        let finished = session.learn(batch);
+        print_progress(&finished, session.record, steps, started);
+
+        if learn_steps % config.rollout.sync_interval.max(1) == 0 {
+            for update_sender in &update_senders {
+                let update = PolicyUpdate {
+                    weights: session.agent.weights(),
+                    n_games: session.agent.n_games,
+                };
+                if update_sender.send(update).is_err() {
+                    panic!("A rollout worker stopped unexpectedly.");
+                }
+            }
+        }
+    }
+}
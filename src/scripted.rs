@@ -0,0 +1,44 @@
+use crate::simulation::{PlayerStepAction, Simulation};
+
+const ACTIONS: [PlayerStepAction; 3] = [
+    PlayerStepAction::Forward,
+    PlayerStepAction::Left,
+    PlayerStepAction::Right,
+];
+
+/// Hand written policy: the safe move getting closest to the nearest apple, preferring to keep
+/// going forward. Safe moves avoid walls, bodies and the heads of the other snakes.
+pub fn scripted_action(sim: &Simulation, index: usize) -> PlayerStepAction {
+    let snake = sim.snake(index);
+    let apple = sim.nearest_apple(index).map(|apple| apple.pos);
+
+    let mut best = (PlayerStepAction::Forward, i32::MAX);
+    for action in ACTIONS {
+        let pos = sim
+            .arena()
+            .next(&snake.head.pos, &action.rotate(&snake.head.orientation));
+        if sim.is_blocked(&pos) {
+            continue;
+        }
+
+        let distance = apple.map_or(0, |apple| {
+            let (dx, dy) = sim.arena().offset(&pos, &apple);
+            dx.abs() + dy.abs()
+        });
+        if distance < best.1 {
+            best = (action, distance);
+        }
+    }
+    best.0
+}
+
+/// Actions of every snake of `sim` when the snake 0 takes `action` and the others follow
+/// [`scripted_action`].
+pub fn with_scripted_opponents(
+    sim: &Simulation,
+    action: PlayerStepAction,
+) -> Vec<PlayerStepAction> {
+    let mut actions = vec![action];
+    actions.extend((1..sim.snakes().len()).map(|index| scripted_action(sim, index)));
+    actions
+}
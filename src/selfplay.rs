@@ -0,0 +1,442 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use tch::Tensor;
+
+use crate::{
+    agent::Agent,
+    cli::RunSettings,
+    config::{AgentConfig, Config, RewardConfig, SelfPlayConfig},
+    env::SnakeGame,
+    model::Snapshot,
+    observation::{Encoder, ObservationEncoder},
+    rollout::RolloutBatch,
+    simulation::{PlayerStepAction, PlayerStepResult, Simulation},
+    train::{print_progress, FinishedGame, TrainingSession},
+    utils::derive_seed,
+};
+
+/// Random stream drawing the seats of the self-play games.
+const SEATING_STREAM: u64 = u64::MAX / 2;
+
+/// Who steers a snake of a self-play game.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Seat {
+    Learner,
+    /// The league checkpoint with this id.
+    League(usize),
+}
+
+struct SelfPlayGame {
+    sim: Simulation,
+    /// Seat of each snake, the snake 0 always being the learner.
+    seats: Vec<Seat>,
+}
+
+/// A frozen copy of the learner.
+struct LeagueMember {
+    /// Checkpoints are numbered in the order they joined, naming their file.
+    id: usize,
+    agent: Agent,
+    rating: f64,
+}
+
+/// Ratings of the learner and of the league checkpoints, saved next to them.
+#[derive(Serialize, Deserialize, Default)]
+struct Ratings {
+    learner: f64,
+    checkpoints: BTreeMap<String, f64>,
+}
+
+/// Past checkpoints of the learner playing as its opponents, rated with the learner by Elo.
+struct League {
+    dir: PathBuf,
+    members: Vec<LeagueMember>,
+    learner_rating: f64,
+    next_id: usize,
+    config: SelfPlayConfig,
+}
+impl League {
+    fn ratings_path(dir: &Path) -> PathBuf {
+        dir.join("ratings.toml")
+    }
+
+    /// Opens the league saved in `dir`, or an empty one.
+    fn open(dir: PathBuf, config: &Config, probe: &SnakeGame) -> Self {
+        let path = Self::ratings_path(&dir);
+        let ratings = if path.exists() {
+            fs::read_to_string(&path)
+                .map_err(|err| err.to_string())
+                .and_then(|content| toml::from_str(&content).map_err(|err| err.to_string()))
+                .unwrap_or_else(|err| panic!("Failed to load league {:?}: {}", path, err))
+        } else {
+            Ratings {
+                learner: config.selfplay.initial_rating,
+                checkpoints: BTreeMap::new(),
+            }
+        };
+
+        let mut members: Vec<LeagueMember> = ratings
+            .checkpoints
+            .iter()
+            .map(|(name, rating)| {
+                let id = name
+                    .parse()
+                    .unwrap_or_else(|_| panic!("Invalid league checkpoint {:?}", name));
+                let path = dir.join(format!("{}.ot", id));
+                LeagueMember {
+                    id,
+                    agent: Agent::load(&path, &config.agent, 0, probe).unwrap_or_else(|err| {
+                        panic!("Failed to load league checkpoint {:?}: {}", path, err)
+                    }),
+                    rating: *rating,
+                }
+            })
+            .collect();
+        members.sort_by_key(|member| member.id);
+
+        Self {
+            dir,
+            next_id: members.last().map_or(0, |member| member.id + 1),
+            members,
+            learner_rating: ratings.learner,
+            config: config.selfplay.clone(),
+        }
+    }
+
+    fn member(&self, id: usize) -> Option<&LeagueMember> {
+        self.members.iter().find(|member| member.id == id)
+    }
+
+    fn rating(&self, seat: Seat) -> f64 {
+        match seat {
+            Seat::Learner => self.learner_rating,
+            Seat::League(id) => self
+                .member(id)
+                .map_or(self.learner_rating, |member| member.rating),
+        }
+    }
+
+    /// Freezes a copy of `learner` into the league, with the rating of the learner, dropping the
+    /// oldest checkpoint, and deleting its file, when full.
+    fn add(
+        &mut self,
+        learner: &Agent,
+        agent_config: &AgentConfig,
+        probe: &SnakeGame,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let id = self.next_id;
+        self.next_id += 1;
+        learner.save(&self.dir.join(format!("{}.ot", id)))?;
+
+        let mut agent = Agent::new(agent_config, 0, probe);
+        agent.load_weights(&learner.weights());
+        self.members.push(LeagueMember {
+            id,
+            agent,
+            rating: self.learner_rating,
+        });
+        let dropped =
+            (self.members.len() > self.config.league_size.max(1)).then(|| self.members.remove(0));
+        // The ratings stop listing a dropped checkpoint before its file goes away.
+        self.save()?;
+        if let Some(dropped) = dropped {
+            fs::remove_file(self.dir.join(format!("{}.ot", dropped.id)))?;
+        }
+        Ok(())
+    }
+
+    fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let ratings = Ratings {
+            learner: self.learner_rating,
+            checkpoints: self
+                .members
+                .iter()
+                .map(|member| (member.id.to_string(), member.rating))
+                .collect(),
+        };
+        fs::create_dir_all(&self.dir)?;
+        Ok(fs::write(
+            Self::ratings_path(&self.dir),
+            toml::to_string_pretty(&ratings)?,
+        )?)
+    }
+
+    /// Draws the seats of a new game of `snakes` snakes.
+    fn draw_seats(&self, snakes: usize, rng: &mut StdRng) -> Vec<Seat> {
+        let mut seats = vec![Seat::Learner];
+        for _ in 1..snakes {
+            let seat = if !self.members.is_empty() && rng.gen_bool(self.config.league_chance) {
+                Seat::League(self.members[rng.gen_range(0..self.members.len())].id)
+            } else {
+                Seat::Learner
+            };
+            seats.push(seat);
+        }
+        seats
+    }
+
+    /// Updates the ratings with the result of a finished game: every pair of snakes of different
+    /// players is a match, won by the higher score, then by the survivor.
+    fn rate(&mut self, sim: &Simulation, seats: &[Seat]) {
+        let outcome = |index: usize| {
+            let snake = sim.snake(index);
+            (snake.punctuation, snake.alive)
+        };
+
+        let mut deltas = vec![0.0; seats.len()];
+        for i in 0..seats.len() {
+            for j in i + 1..seats.len() {
+                if seats[i] == seats[j] {
+                    continue;
+                }
+                let expected = 1.0
+                    / (1.0 + 10f64.powf((self.rating(seats[j]) - self.rating(seats[i])) / 400.0));
+                let actual = match outcome(i).cmp(&outcome(j)) {
+                    std::cmp::Ordering::Greater => 1.0,
+                    std::cmp::Ordering::Equal => 0.5,
+                    std::cmp::Ordering::Less => 0.0,
+                };
+                let delta = self.config.elo_k * (actual - expected);
+                deltas[i] += delta;
+                deltas[j] -= delta;
+            }
+        }
+
+        for (seat, delta) in seats.iter().zip(deltas) {
+            match seat {
+                Seat::Learner => self.learner_rating += delta,
+                Seat::League(id) => {
+                    if let Some(member) = self.members.iter_mut().find(|member| member.id == *id) {
+                        member.rating += delta;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Training where the agent plays multi-snake games against itself and against a league of its
+/// past checkpoints. Every snake of the learner stores its own snapshots.
+pub struct SelfPlaySession {
+    pub session: TrainingSession,
+    games: Vec<SelfPlayGame>,
+    league: League,
+    /// Only gives the spaces of the league agents.
+    probe: SnakeGame,
+    rewards: RewardConfig,
+    starvation_factor: usize,
+    observation: Encoder,
+    agent_config: AgentConfig,
+    snapshot_interval: usize,
+    next_snapshot: usize,
+    rng: StdRng,
+}
+impl SelfPlaySession {
+    pub fn new(settings: &RunSettings, config: &Config) -> Self {
+        let seed = settings.seed.expect("seed resolved");
+        let probe = SnakeGame::new(seed, config);
+        assert!(
+            probe.sim.snakes().len() >= 2,
+            "Self-play needs at least 2 snakes, set env.snakes or use a level with more spawns."
+        );
+
+        let session = TrainingSession::new(settings, config, &probe);
+        let league = League::open(settings.out_dir.join("league"), config, &probe);
+        let mut rng = StdRng::seed_from_u64(derive_seed(seed, SEATING_STREAM));
+        let games = (0..settings.scenes as u64)
+            .map(|index| {
+                let sim = SnakeGame::new(derive_seed(seed, index), config).sim;
+                let seats = league.draw_seats(sim.snakes().len(), &mut rng);
+                SelfPlayGame { sim, seats }
+            })
+            .collect();
+
+        let snapshot_interval = config.selfplay.snapshot_interval.max(1);
+        Self {
+            next_snapshot: session.agent.n_games + snapshot_interval,
+            session,
+            games,
+            league,
+            probe,
+            rewards: config.rewards.clone(),
+            starvation_factor: config.env.starvation_factor,
+            observation: config.env.encoder(),
+            agent_config: config.agent.clone(),
+            snapshot_interval,
+            rng,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.games.len()
+    }
+
+    pub fn sim(&self, index: usize) -> &Simulation {
+        &self.games[index].sim
+    }
+
+    /// Plays one step on every game, trains on the snapshots of the learner snakes and returns
+    /// the games that finished, already reset, scored by their best learner snake.
+    pub fn step(&mut self) -> Vec<FinishedGame> {
+        let mut owners = Vec::new();
+        let mut states_old = Vec::new();
+        for (game_index, game) in self.games.iter().enumerate() {
+            for (index, seat) in game.seats.iter().enumerate() {
+                if *seat == Seat::Learner && game.sim.snake(index).alive {
+                    owners.push((game_index, index));
+                    states_old.push(self.observation.encode(&game.sim, index));
+                }
+            }
+        }
+        let final_moves = self.session.agent.get_actions(
+            &Tensor::from_slice(&states_old.concat()).view([states_old.len() as i64, -1]),
+        );
+
+        let mut actions: Vec<Vec<PlayerStepAction>> = self
+            .games
+            .iter()
+            .map(|game| {
+                game.seats
+                    .iter()
+                    .enumerate()
+                    .map(|(index, seat)| match seat {
+                        Seat::League(id) if game.sim.snake(index).alive => {
+                            let agent = &self.league.member(*id).unwrap().agent;
+                            SnakeGame::action(Agent::action_index(
+                                &agent
+                                    .get_greedy_action(&self.observation.encode(&game.sim, index)),
+                            ))
+                        }
+                        _ => PlayerStepAction::Forward,
+                    })
+                    .collect()
+            })
+            .collect();
+        for ((game_index, index), final_move) in owners.iter().zip(&final_moves) {
+            actions[*game_index][*index] = SnakeGame::action(Agent::action_index(final_move));
+        }
+
+        let mut results = Vec::with_capacity(self.games.len());
+        let mut overs = Vec::with_capacity(self.games.len());
+        for (game, actions) in self.games.iter_mut().zip(&actions) {
+            let sim = &mut game.sim;
+            let game_results = sim.step_all(actions);
+            let longest = sim
+                .snakes()
+                .iter()
+                .filter(|snake| snake.alive)
+                .map(|snake| snake.len())
+                .max()
+                .unwrap_or(0);
+            let starved = sim.frame_iteration > self.starvation_factor * longest;
+            let learner_alive = game
+                .seats
+                .iter()
+                .zip(sim.snakes())
+                .any(|(seat, snake)| *seat == Seat::Learner && snake.alive);
+            overs.push((sim.is_over() || !learner_alive || starved, starved));
+            results.push(game_results);
+        }
+
+        let mut snapshots = Vec::with_capacity(owners.len());
+        for (((game_index, index), state), final_move) in
+            owners.into_iter().zip(states_old).zip(final_moves)
+        {
+            let (over, starved) = overs[game_index];
+            let result = results[game_index][index];
+            let (reward, died) = match result {
+                PlayerStepResult::Nothing if starved => (self.rewards.starvation, true),
+                result => self.rewards.of(result),
+            };
+            snapshots.push(Snapshot {
+                state,
+                action: final_move,
+                reward,
+                next_state: self.observation.encode(&self.games[game_index].sim, index),
+                done: died || over,
+            });
+        }
+
+        let mut done_scores = Vec::new();
+        for (game, (over, _)) in self.games.iter_mut().zip(overs) {
+            if !over {
+                continue;
+            }
+            self.league.rate(&game.sim, &game.seats);
+            done_scores.push(
+                game.seats
+                    .iter()
+                    .zip(game.sim.snakes())
+                    .filter(|(seat, _)| **seat == Seat::Learner)
+                    .map(|(_, snake)| snake.punctuation)
+                    .max()
+                    .unwrap_or(0),
+            );
+            game.sim.reset();
+            game.seats = self
+                .league
+                .draw_seats(game.sim.snakes().len(), &mut self.rng);
+        }
+
+        let finished = self.session.learn(RolloutBatch {
+            snapshots,
+            done_scores,
+        });
+
+        if self.session.agent.n_games >= self.next_snapshot {
+            self.next_snapshot = self.session.agent.n_games + self.snapshot_interval;
+            self.freeze_learner();
+        }
+        finished
+    }
+
+    /// Adds the learner to the league. Running games whose opponent left the league go on with
+    /// the learner in its seat.
+    fn freeze_learner(&mut self) {
+        self.league
+            .add(&self.session.agent, &self.agent_config, &self.probe)
+            .unwrap();
+        for game in &mut self.games {
+            for seat in &mut game.seats {
+                if let Seat::League(id) = seat {
+                    if self.league.member(*id).is_none() {
+                        *seat = Seat::Learner;
+                    }
+                }
+            }
+        }
+
+        let ratings: Vec<String> = self
+            .league
+            .members
+            .iter()
+            .map(|member| format!("{}: {:.0}", member.id, member.rating))
+            .collect();
+        println!(
+            "League: learner {:.0}, checkpoints {}",
+            self.league.learner_rating,
+            ratings.join(", ")
+        );
+    }
+}
+
+/// Headless self-play training, see [`SelfPlaySession`].
+pub fn train_self_play(settings: RunSettings, config: Config) {
+    let mut session = SelfPlaySession::new(&settings, &config);
+
+    let started = Instant::now();
+    let mut steps: usize = 0;
+    loop {
+        let finished = session.step();
+        steps += session.len();
+
+        print_progress(&finished, session.session.record, steps, started);
+    }
+}
@@ -0,0 +1,883 @@
+use std::collections::HashMap;
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    level::Level,
+    obstacles,
+    utils::{derive_seed, IndexedSet},
+};
+
+#[derive(Debug, Eq, PartialEq, Hash, Clone, Copy)]
+pub struct GridPos {
+    pub x: i32,
+    pub y: i32,
+}
+
+impl GridPos {
+    pub fn new(x: i32, y: i32) -> Self {
+        Self { x, y }
+    }
+}
+
+/// Playable cells of a board, centered on the origin. A ring of walls surrounds them, unless the
+/// borders wrap around.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Arena {
+    pub min: GridPos,
+    pub max: GridPos,
+    /// Leaving a border enters the arena again from the opposite one.
+    pub wrap: bool,
+}
+impl Arena {
+    pub fn new(width: i32, height: i32, wrap: bool) -> Self {
+        assert!(
+            width >= 1 && height >= 1,
+            "The arena must have cells, got {}x{}.",
+            width,
+            height
+        );
+        let min = GridPos::new(-width / 2, -height / 2);
+        Self {
+            min,
+            max: GridPos::new(min.x + width - 1, min.y + height - 1),
+            wrap,
+        }
+    }
+
+    pub fn width(&self) -> i32 {
+        self.max.x - self.min.x + 1
+    }
+
+    pub fn height(&self) -> i32 {
+        self.max.y - self.min.y + 1
+    }
+
+    /// Whether `pos` is one of the playable cells.
+    pub fn contains(&self, pos: &GridPos) -> bool {
+        (self.min.x..=self.max.x).contains(&pos.x) && (self.min.y..=self.max.y).contains(&pos.y)
+    }
+
+    /// `pos` brought back inside the arena when the borders wrap, unchanged otherwise.
+    pub fn wrap_pos(&self, pos: GridPos) -> GridPos {
+        if !self.wrap {
+            return pos;
+        }
+        GridPos::new(
+            self.min.x + (pos.x - self.min.x).rem_euclid(self.width()),
+            self.min.y + (pos.y - self.min.y).rem_euclid(self.height()),
+        )
+    }
+
+    /// Shortest offset from `from` to `to`, going across the borders when they wrap.
+    pub fn offset(&self, from: &GridPos, to: &GridPos) -> (i32, i32) {
+        let shortest = |delta: i32, size: i32| {
+            if self.wrap && delta.abs() * 2 > size {
+                delta - size * delta.signum()
+            } else {
+                delta
+            }
+        };
+        (
+            shortest(to.x - from.x, self.width()),
+            shortest(to.y - from.y, self.height()),
+        )
+    }
+
+    /// Cell reached moving from `pos` towards `orientation`.
+    pub fn next(&self, pos: &GridPos, orientation: &SnakeOrientation) -> GridPos {
+        self.wrap_pos(orientation.next(pos))
+    }
+}
+impl Default for Arena {
+    fn default() -> Self {
+        Self::new(41, 41, false)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum SnakeOrientation {
+    Up,
+    Down,
+    Left,
+    Right,
+}
+impl SnakeOrientation {
+    pub fn next(&self, pos: &GridPos) -> GridPos {
+        match &self {
+            SnakeOrientation::Up => GridPos::new(pos.x, pos.y + 1),
+            SnakeOrientation::Down => GridPos::new(pos.x, pos.y - 1),
+            SnakeOrientation::Left => GridPos::new(pos.x - 1, pos.y),
+            SnakeOrientation::Right => GridPos::new(pos.x + 1, pos.y),
+        }
+    }
+
+    pub fn opposite(&self) -> Self {
+        match self {
+            Self::Up => Self::Down,
+            Self::Down => Self::Up,
+            Self::Left => Self::Right,
+            Self::Right => Self::Left,
+        }
+    }
+
+    pub fn apply_to_action(&self, applied_orientation: &Self) -> Option<PlayerStepAction> {
+        // Most elegant approach? No, but it's the fastest.
+        match self {
+            Self::Up => match applied_orientation {
+                Self::Up => Some(PlayerStepAction::Forward),
+                Self::Down => None,
+                Self::Left => Some(PlayerStepAction::Left),
+                Self::Right => Some(PlayerStepAction::Right),
+            },
+            Self::Down => match applied_orientation {
+                Self::Up => None,
+                Self::Down => Some(PlayerStepAction::Forward),
+                Self::Left => Some(PlayerStepAction::Right),
+                Self::Right => Some(PlayerStepAction::Left),
+            },
+            Self::Left => match applied_orientation {
+                Self::Up => Some(PlayerStepAction::Right),
+                Self::Down => Some(PlayerStepAction::Left),
+                Self::Left => Some(PlayerStepAction::Forward),
+                Self::Right => None,
+            },
+            Self::Right => match applied_orientation {
+                Self::Up => Some(PlayerStepAction::Left),
+                Self::Down => Some(PlayerStepAction::Right),
+                Self::Left => None,
+                Self::Right => Some(PlayerStepAction::Forward),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlayerStepAction {
+    Forward,
+    Left,
+    Right,
+}
+impl PlayerStepAction {
+    pub fn rotate(&self, orientation: &SnakeOrientation) -> SnakeOrientation {
+        // Most elegant approach? No, but it's the fastest.
+        match self {
+            PlayerStepAction::Forward => *orientation,
+            PlayerStepAction::Left => match orientation {
+                SnakeOrientation::Up => SnakeOrientation::Left,
+                SnakeOrientation::Down => SnakeOrientation::Right,
+                SnakeOrientation::Left => SnakeOrientation::Down,
+                SnakeOrientation::Right => SnakeOrientation::Up,
+            },
+            PlayerStepAction::Right => match orientation {
+                SnakeOrientation::Up => SnakeOrientation::Right,
+                SnakeOrientation::Down => SnakeOrientation::Left,
+                SnakeOrientation::Left => SnakeOrientation::Up,
+                SnakeOrientation::Right => SnakeOrientation::Down,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlayerStepResult {
+    Nothing,
+    AppleEaten,
+    /// A golden apple was eaten, worth [`FoodSettings::golden_score`] points.
+    GoldenAppleEaten,
+    /// A poison apple was eaten, shrinking the snake, or killing it when `fatal`.
+    PoisonEaten {
+        fatal: bool,
+    },
+    Collision,
+    /// The last apple was eaten and the snakes fill the whole arena.
+    Won,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColliderVariant {
+    Wall,
+    /// Body part of the snake at this index.
+    SnakeBody(usize),
+}
+
+pub struct SnakeHead {
+    pub orientation: SnakeOrientation,
+    pub pos: GridPos,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FoodKind {
+    /// Grows the snake by one and scores a point.
+    Regular,
+    /// Grows the snake by one and scores more, but despawns after a while.
+    Golden,
+    /// Shrinks the snake, or ends the game.
+    Poison,
+}
+
+/// Odds and effects of the special apples. Every apple is regular by default.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct FoodSettings {
+    /// Probability of a spawning apple to be golden.
+    pub golden_chance: f32,
+    pub golden_score: usize,
+    /// Frames a golden apple stays before respawning elsewhere.
+    pub golden_lifetime: usize,
+    /// Probability of a spawning apple to be poison.
+    pub poison_chance: f32,
+    /// Body parts a poison apple removes; 0, or a snake too short to lose them, ends the game.
+    pub poison_shrink: usize,
+}
+impl Default for FoodSettings {
+    fn default() -> Self {
+        Self {
+            golden_chance: 0.0,
+            golden_score: 5,
+            golden_lifetime: 50,
+            poison_chance: 0.0,
+            poison_shrink: 1,
+        }
+    }
+}
+
+pub struct Apple {
+    pub pos: GridPos,
+    pub kind: FoodKind,
+    /// Frames left before a golden apple despawns.
+    pub frames_left: Option<usize>,
+}
+
+/// Random arena cells tried for an apple before sampling the free cells.
+const APPLE_CELL_TRIES: usize = 32;
+
+/// Most snakes sharing a board.
+pub const MAX_SNAKES: usize = 4;
+
+pub struct Snake {
+    pub head: SnakeHead,
+    body_parts: Vec<GridPos>,
+    pub punctuation: usize,
+    /// Dead snakes leave the board until the next reset.
+    pub alive: bool,
+}
+impl Snake {
+    pub fn len(&self) -> usize {
+        self.body_parts.len() + 1
+    }
+
+    /// Body parts ordered from the tail to the part right behind the head.
+    pub fn body_parts(&self) -> &[GridPos] {
+        &self.body_parts
+    }
+}
+
+/// The snake rules, without any rendering. A Bevy `Scene` is only a view synced from this.
+///
+/// Snakes move together: a snake dies moving into a wall or into any body, including the neck
+/// of another snake, and snakes moving into the same cell all die.
+pub struct Simulation {
+    snakes: Vec<Snake>,
+    apples: Vec<Apple>,
+    level: Level,
+    /// Index in the apple spawn list of the level of the next cell to try.
+    next_level_apple: usize,
+    colliders: HashMap<GridPos, ColliderVariant>,
+    walls: Vec<GridPos>,
+    /// Random walls of the current game, on top of the level ones.
+    obstacles: Vec<GridPos>,
+    /// Arena cells that are neither colliders, snake heads nor apples, where apples can spawn.
+    free_cells: IndexedSet<GridPos>,
+    pub frame_iteration: usize,
+    rng: StdRng,
+    /// Seed of the apples of the current game, each apple drawing from its own stream.
+    apple_seed: u64,
+    /// Apples spawned in the current game.
+    apples_spawned: u64,
+}
+impl Simulation {
+    /// Creates a game on `level` whose apple placements are fully determined by `seed`.
+    pub fn new(seed: u64, level: Level) -> Self {
+        let arena = level.arena;
+        let mut walls = level.walls.clone();
+        if !arena.wrap {
+            for x in arena.min.x - 1..=arena.max.x + 1 {
+                walls.push(GridPos::new(x, arena.min.y - 1));
+                walls.push(GridPos::new(x, arena.max.y + 1));
+            }
+            for y in (arena.min.y - 1 + 1)..arena.max.y + 1 {
+                walls.push(GridPos::new(arena.min.x - 1, y));
+                walls.push(GridPos::new(arena.max.x + 1, y));
+            }
+        }
+
+        let snakes = level
+            .spawns
+            .iter()
+            .map(|spawn| Snake {
+                head: SnakeHead {
+                    orientation: spawn.orientation,
+                    pos: spawn.head,
+                },
+                body_parts: Vec::new(),
+                punctuation: 0,
+                alive: true,
+            })
+            .collect();
+        let mut exit = Self {
+            snakes,
+            apples: Vec::new(),
+            level,
+            next_level_apple: 0,
+            colliders: HashMap::new(),
+            walls: Vec::new(),
+            obstacles: Vec::new(),
+            free_cells: IndexedSet::new(),
+            frame_iteration: 0,
+            rng: StdRng::seed_from_u64(seed),
+            apple_seed: 0,
+            apples_spawned: 0,
+        };
+        for pos in walls {
+            exit.push_collider(ColliderVariant::Wall, pos);
+            exit.walls.push(pos);
+        }
+        exit.reset();
+
+        exit
+    }
+
+    fn push_collider(&mut self, variant: ColliderVariant, pos: GridPos) {
+        if let Some(replaced) = self.colliders.insert(pos, variant) {
+            panic!("Collider override. Replaced: {:?} at {:?}", replaced, pos)
+        }
+    }
+
+    pub fn snakes(&self) -> &[Snake] {
+        &self.snakes
+    }
+
+    pub fn snake(&self, index: usize) -> &Snake {
+        &self.snakes[index]
+    }
+
+    pub fn apples(&self) -> &[Apple] {
+        &self.apples
+    }
+
+    /// The apple closest to the head of the snake at `index`, across the borders when they wrap,
+    /// `None` only once the snakes filled the arena.
+    pub fn nearest_apple(&self, index: usize) -> Option<&Apple> {
+        let head = &self.snakes[index].head.pos;
+        self.apples.iter().min_by_key(|apple| {
+            let (dx, dy) = self.level.arena.offset(head, &apple.pos);
+            dx.abs() + dy.abs()
+        })
+    }
+
+    /// Whether every snake died or no apple is left.
+    pub fn is_over(&self) -> bool {
+        self.apples.is_empty() || self.snakes.iter().all(|snake| !snake.alive)
+    }
+
+    pub fn level(&self) -> &Level {
+        &self.level
+    }
+
+    pub fn arena(&self) -> &Arena {
+        &self.level.arena
+    }
+
+    pub fn walls(&self) -> &[GridPos] {
+        &self.walls
+    }
+
+    pub fn obstacles(&self) -> &[GridPos] {
+        &self.obstacles
+    }
+
+    pub fn collider(&self, pos: &GridPos) -> Option<&ColliderVariant> {
+        self.colliders.get(pos)
+    }
+
+    pub fn is_collision(&self, pos: &GridPos) -> bool {
+        self.colliders.contains_key(pos)
+    }
+
+    /// Whether moving into `pos` kills: a collider or the head of a living snake.
+    pub fn is_blocked(&self, pos: &GridPos) -> bool {
+        self.is_collision(pos)
+            || self
+                .snakes
+                .iter()
+                .any(|snake| snake.alive && snake.head.pos == *pos)
+    }
+
+    /// A new apple on the next free cell of the apple spawn list of the level, else on a random
+    /// free cell, or `None` when the snakes and the apples fill the arena. The cell is taken from
+    /// the free ones.
+    ///
+    /// The n-th apple of a game only depends on the seed and on which cells are free, so games
+    /// sharing a seed get the same apples as long as their cells are free in both.
+    fn random_apple(&mut self) -> Option<Apple> {
+        let mut rng = StdRng::seed_from_u64(derive_seed(self.apple_seed, self.apples_spawned));
+        self.apples_spawned += 1;
+        let pos = self.pick_apple_pos(&mut rng)?;
+        self.free_cells.remove(&pos);
+
+        let food = self.level.food;
+        let mut kind = FoodKind::Regular;
+        // Levels without special food keep the random stream of the apple positions untouched.
+        if food.golden_chance > 0.0 || food.poison_chance > 0.0 {
+            let roll: f32 = rng.gen();
+            if roll < food.golden_chance {
+                kind = FoodKind::Golden;
+            } else if roll < food.golden_chance + food.poison_chance {
+                kind = FoodKind::Poison;
+            }
+        }
+        Some(Apple {
+            pos,
+            kind,
+            frames_left: (kind == FoodKind::Golden).then_some(food.golden_lifetime),
+        })
+    }
+
+    /// Replaces the apple at `index` by a new one, or removes it when there is no room left.
+    fn respawn_apple(&mut self, index: usize) {
+        match self.random_apple() {
+            Some(apple) => self.apples[index] = apple,
+            None => {
+                self.apples.swap_remove(index);
+            }
+        }
+    }
+
+    fn pick_apple_pos(&mut self, rng: &mut StdRng) -> Option<GridPos> {
+        let apples = &self.level.apples;
+        for _ in 0..apples.len() {
+            let pos = apples[self.next_level_apple];
+            self.next_level_apple = (self.next_level_apple + 1) % apples.len();
+            if self.free_cells.contains(&pos) {
+                return Some(pos);
+            }
+        }
+
+        // Cells drawn from the whole arena do not depend on the other free cells, unlike the
+        // fallback once the arena fills up.
+        let arena = self.level.arena;
+        for _ in 0..APPLE_CELL_TRIES {
+            let pos = GridPos::new(
+                rng.gen_range(arena.min.x..=arena.max.x),
+                rng.gen_range(arena.min.y..=arena.max.y),
+            );
+            if self.free_cells.contains(&pos) {
+                return Some(pos);
+            }
+        }
+        self.free_cells.choose(rng)
+    }
+
+    /// Moves the head of the snake at `index` to `new_head_pos`, growing the snake or dragging
+    /// its tail along.
+    fn move_head(
+        &mut self,
+        index: usize,
+        orientation: SnakeOrientation,
+        new_head_pos: GridPos,
+        grow: bool,
+    ) {
+        let snake = &mut self.snakes[index];
+        snake.head.orientation = orientation;
+        let old_head_pos = std::mem::replace(&mut snake.head.pos, new_head_pos);
+        self.free_cells.remove(&new_head_pos);
+
+        if grow {
+            self.push_collider(ColliderVariant::SnakeBody(index), old_head_pos);
+        } else {
+            let tail_pos = snake.body_parts.remove(0);
+            let last_body_part = self.colliders.remove(&tail_pos).unwrap();
+            self.free_cells.insert(tail_pos);
+            self.colliders.insert(old_head_pos, last_body_part);
+        }
+        self.snakes[index].body_parts.push(old_head_pos);
+    }
+
+    /// Removes the snake at `index` from the board.
+    fn kill(&mut self, index: usize) {
+        let snake = &mut self.snakes[index];
+        snake.alive = false;
+        for pos in snake.body_parts.drain(..) {
+            self.colliders.remove(&pos).unwrap();
+            self.free_cells.insert(pos);
+        }
+        self.free_cells.insert(snake.head.pos);
+    }
+
+    /// Counts down the golden apples, respawning the expired ones.
+    fn age_apples(&mut self) {
+        for index in (0..self.apples.len()).rev() {
+            let apple = &mut self.apples[index];
+            if let Some(frames_left) = &mut apple.frames_left {
+                *frames_left = frames_left.saturating_sub(1);
+                if *frames_left == 0 {
+                    // The expired cell may be the only room left for the new apple.
+                    self.free_cells.insert(apple.pos);
+                    self.respawn_apple(index);
+                }
+            }
+        }
+    }
+
+    /// Moves every snake at once, `actions[i]` steering the snake `i`. Dead snakes ignore their
+    /// action and get [`PlayerStepResult::Nothing`].
+    pub fn step_all(&mut self, actions: &[PlayerStepAction]) -> Vec<PlayerStepResult> {
+        assert_eq!(
+            actions.len(),
+            self.snakes.len(),
+            "One action is needed per snake."
+        );
+
+        let targets: Vec<Option<(SnakeOrientation, GridPos)>> = self
+            .snakes
+            .iter()
+            .zip(actions)
+            .map(|(snake, action)| {
+                snake.alive.then(|| {
+                    let orientation = action.rotate(&snake.head.orientation);
+                    let pos = self.level.arena.next(&snake.head.pos, &orientation);
+                    (orientation, pos)
+                })
+            })
+            .collect();
+
+        let mut results = vec![PlayerStepResult::Nothing; self.snakes.len()];
+        let mut dead = Vec::new();
+        for (index, target) in targets.iter().enumerate() {
+            let Some((_, pos)) = target else {
+                continue;
+            };
+            let head_to_head = self.snakes.iter().enumerate().any(|(other, snake)| {
+                other != index
+                    && snake.alive
+                    && (snake.head.pos == *pos
+                        || targets[other].is_some_and(|(_, other_pos)| other_pos == *pos))
+            });
+            if head_to_head || self.colliders.contains_key(pos) {
+                results[index] = PlayerStepResult::Collision;
+                dead.push(index);
+                continue;
+            }
+
+            let shrink = self.level.food.poison_shrink;
+            let poisoned = self
+                .apples
+                .iter()
+                .any(|apple| apple.pos == *pos && apple.kind == FoodKind::Poison);
+            // A snake keeps at least one body part behind its head.
+            if poisoned && (shrink == 0 || shrink >= self.snakes[index].body_parts.len()) {
+                results[index] = PlayerStepResult::PoisonEaten { fatal: true };
+                dead.push(index);
+            }
+        }
+
+        let mut eaten_apples = Vec::new();
+        for (index, target) in targets.into_iter().enumerate() {
+            let Some((orientation, pos)) = target else {
+                continue;
+            };
+            if dead.contains(&index) {
+                continue;
+            }
+            let Some(eaten) = self.apples.iter().position(|apple| apple.pos == pos) else {
+                self.move_head(index, orientation, pos, false);
+                continue;
+            };
+
+            results[index] = match self.apples[eaten].kind {
+                FoodKind::Regular => {
+                    self.move_head(index, orientation, pos, true);
+                    self.snakes[index].punctuation += 1;
+                    PlayerStepResult::AppleEaten
+                }
+                FoodKind::Golden => {
+                    self.move_head(index, orientation, pos, true);
+                    self.snakes[index].punctuation += self.level.food.golden_score;
+                    PlayerStepResult::GoldenAppleEaten
+                }
+                FoodKind::Poison => {
+                    self.move_head(index, orientation, pos, false);
+                    let shrink = self.level.food.poison_shrink;
+                    for tail_pos in self.snakes[index].body_parts.drain(..shrink) {
+                        self.colliders.remove(&tail_pos).unwrap();
+                        self.free_cells.insert(tail_pos);
+                    }
+                    PlayerStepResult::PoisonEaten { fatal: false }
+                }
+            };
+
+            eaten_apples.push((eaten, index));
+        }
+
+        // Apples respawn once every snake moved, so none lands where a later snake was heading.
+        // Removing an apple moves the last one into its slot, hence the decreasing order.
+        eaten_apples.sort_unstable_by(|a, b| b.cmp(a));
+        for (eaten, index) in eaten_apples {
+            // Near the end, eaten apples only respawn while there is room left.
+            self.respawn_apple(eaten);
+            if self.apples.is_empty() {
+                results[index] = PlayerStepResult::Won;
+            }
+        }
+
+        for index in dead {
+            self.kill(index);
+        }
+        if !self.apples.is_empty() {
+            self.age_apples();
+        }
+        self.frame_iteration += 1;
+        results
+    }
+
+    pub fn reset(&mut self) {
+        for snake in &mut self.snakes {
+            for body_pos in &snake.body_parts {
+                self.colliders.remove(body_pos).unwrap();
+            }
+            snake.body_parts.clear();
+        }
+
+        for pos in &self.obstacles {
+            self.colliders.remove(pos).unwrap();
+        }
+        self.obstacles = obstacles::generate(&self.level, &mut self.rng);
+        for pos in self.obstacles.clone() {
+            self.push_collider(ColliderVariant::Wall, pos);
+        }
+
+        for (index, spawn) in self.level.spawns.clone().into_iter().enumerate() {
+            let [tail, body, head] = spawn.snake_cells(&self.level.arena);
+            for pos in [tail, body] {
+                self.push_collider(ColliderVariant::SnakeBody(index), pos);
+            }
+            let snake = &mut self.snakes[index];
+            snake.body_parts = vec![tail, body];
+            snake.head = SnakeHead {
+                orientation: spawn.orientation,
+                pos: head,
+            };
+            snake.punctuation = 0;
+            snake.alive = true;
+        }
+
+        self.free_cells.clear();
+        let arena = self.level.arena;
+        for y in arena.min.y..=arena.max.y {
+            for x in arena.min.x..=arena.max.x {
+                let pos = GridPos::new(x, y);
+                let is_head = self.snakes.iter().any(|snake| snake.head.pos == pos);
+                if !self.colliders.contains_key(&pos) && !is_head {
+                    self.free_cells.insert(pos);
+                }
+            }
+        }
+        self.next_level_apple = 0;
+        self.apple_seed = self.rng.gen();
+        self.apples_spawned = 0;
+        self.apples.clear();
+        for _ in 0..self.level.apple_count.max(1) {
+            let Some(apple) = self.random_apple() else {
+                break;
+            };
+            self.apples.push(apple);
+        }
+        assert!(!self.apples.is_empty(), "The arena has room for an apple.");
+
+        self.frame_iteration = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use PlayerStepAction::{Forward, Left};
+    use PlayerStepResult::{Collision, Nothing};
+
+    fn game(level: &str) -> Simulation {
+        Simulation::new(0, Level::parse(level, false).unwrap())
+    }
+
+    /// Position of the cell at `column`, `row` of the level grid.
+    fn cell(sim: &Simulation, column: i32, row: i32) -> GridPos {
+        let arena = sim.arena();
+        GridPos::new(arena.min.x + column, arena.max.y - row)
+    }
+
+    #[test]
+    fn moving_into_a_wall_kills() {
+        let mut sim = game("apples: 2,2\n^..\n...\n...\n");
+        assert_eq!(sim.step_all(&[Forward]), [Collision]);
+        assert!(!sim.snake(0).alive);
+        assert!(sim.is_over());
+    }
+
+    #[test]
+    fn moving_into_a_body_kills() {
+        let mut sim = game("apples: 0,4\n.....\n...>.\n..^..\n.....\n.....\n");
+        assert_eq!(
+            sim.snake(0).body_parts(),
+            [cell(&sim, 1, 1), cell(&sim, 2, 1)]
+        );
+        assert_eq!(sim.step_all(&[Forward, Forward]), [Nothing, Collision]);
+        assert!(sim.snake(0).alive);
+        assert!(!sim.snake(1).alive);
+        // The dead snake leaves the board.
+        assert!(!sim.is_collision(&cell(&sim, 2, 3)));
+    }
+
+    #[test]
+    fn moving_into_the_own_tail_kills() {
+        // Heading up in a 3 cells high wrapping arena, the head faces its own tail.
+        let mut sim = Simulation::new(0, Level::empty(Arena::new(3, 3, true), 1));
+        assert_eq!(sim.step_all(&[Forward]), [Collision]);
+    }
+
+    #[test]
+    fn turning_moves_the_head_and_drags_the_tail() {
+        let mut sim = game("apples: 0,3\n...\n.^.\n...\n...\n");
+        let neck = sim.snake(0).head.pos;
+        assert_eq!(sim.step_all(&[Left]), [Nothing]);
+        let snake = sim.snake(0);
+        assert_eq!(snake.head.pos, cell(&sim, 0, 1));
+        assert_eq!(snake.head.orientation, SnakeOrientation::Left);
+        assert_eq!(snake.body_parts(), [cell(&sim, 1, 2), neck]);
+        assert!(!sim.is_collision(&cell(&sim, 1, 3)));
+    }
+
+    #[test]
+    fn filling_the_board_wins() {
+        // Going round the only cycle of a 2x3 arena eats every apple, wherever it spawns.
+        let mut sim = Simulation::new(0, Level::empty(Arena::new(2, 3, false), 1));
+        let mut results = vec![Nothing];
+        for action in [Left, Left, Forward].into_iter().cycle().take(100) {
+            results = sim.step_all(&[action]);
+            if !matches!(results[..], [Nothing | PlayerStepResult::AppleEaten]) {
+                break;
+            }
+        }
+        assert_eq!(results, [PlayerStepResult::Won]);
+        assert_eq!(sim.snake(0).len(), 6);
+        assert!(sim.is_over());
+    }
+
+    #[test]
+    fn wrapping_borders_lead_to_the_opposite_side() {
+        let mut sim = Simulation::new(0, Level::empty(Arena::new(3, 4, true), 1));
+        assert!(sim.walls().is_empty());
+        assert_eq!(sim.snake(0).head.pos.y, sim.arena().max.y);
+        assert_ne!(sim.step_all(&[Forward]), [Collision]);
+        assert_eq!(sim.snake(0).head.pos, GridPos::new(0, sim.arena().min.y));
+        assert!(sim.snake(0).alive);
+    }
+
+    #[test]
+    fn expired_golden_apples_respawn_on_their_own_cell() {
+        let mut sim = game("apple_count: 2\napples: 0,0 0,4\n.\n^\n.\n.\n.\n");
+        let golden = cell(&sim, 0, 4);
+        let apple = &mut sim.apples[1];
+        assert_eq!(apple.pos, golden);
+        apple.kind = FoodKind::Golden;
+        apple.frames_left = Some(1);
+
+        // Growing fills every other cell as the golden apple expires.
+        assert_eq!(sim.step_all(&[Forward]), [PlayerStepResult::AppleEaten]);
+        assert_eq!(sim.apples().len(), 1);
+        assert_eq!(sim.apples()[0].pos, golden);
+        assert!(!sim.is_over());
+    }
+
+    #[test]
+    fn nearest_apple_counts_across_wrapping_borders() {
+        let mut sim = game(
+            "wrap: true\napple_count: 2\napples: 1,7 0,4\n...\n.^.\n...\n...\n...\n...\n...\n...\n",
+        );
+        assert_eq!(sim.nearest_apple(0).unwrap().pos, cell(&sim, 1, 7));
+        assert_eq!(sim.step_all(&[Forward]), [Nothing]);
+        assert_eq!(sim.step_all(&[Forward]), [PlayerStepResult::AppleEaten]);
+    }
+
+    #[test]
+    fn moving_into_a_head_kills() {
+        let mut sim = game("apples: 0,3\n......\n..>^..\n......\n......\n");
+        assert_eq!(sim.step_all(&[Forward, Forward]), [Collision, Nothing]);
+    }
+
+    #[test]
+    fn heads_moving_into_the_same_cell_both_die() {
+        let mut sim = game("apples: 0,0\n.......\n..>.<..\n.......\n");
+        assert_eq!(sim.step_all(&[Forward, Forward]), [Collision, Collision]);
+        assert!(sim.is_over());
+    }
+
+    #[test]
+    fn heads_swapping_cells_both_die() {
+        let mut sim = game("apples: 0,0\n......\n..><..\n......\n");
+        assert_eq!(sim.step_all(&[Forward, Forward]), [Collision, Collision]);
+    }
+
+    #[test]
+    fn apples_eaten_by_a_snake_respawn_after_every_snake_moved() {
+        let mut sim = game(
+            "poison: 1 1\napples: 5,2 1,1 5,1\n.......\n.......\n.......\n.^...^.\n.......\n.......\n",
+        );
+        let poisoned = PlayerStepResult::PoisonEaten { fatal: false };
+        assert_eq!(sim.step_all(&[Forward, Forward]), [Nothing, poisoned]);
+        // The apple eaten by the first snake respawns after the second one moved into its cell.
+        assert_eq!(sim.step_all(&[Forward, Forward]), [poisoned, Nothing]);
+        assert_eq!(sim.snake(1).len(), 2);
+        for apple in sim.apples() {
+            assert!(!sim.is_collision(&apple.pos));
+            assert!(sim.snakes().iter().all(|snake| snake.head.pos != apple.pos));
+        }
+        sim.step_all(&[Left, Left]);
+    }
+
+    #[test]
+    fn poison_shrinks_long_snakes_and_kills_short_ones() {
+        let level = "apples: 1,0\n...\n.^.\n...\n...\n";
+
+        let mut sim = game(&format!("poison: 1 1\n{}", level));
+        assert_eq!(sim.apples()[0].kind, FoodKind::Poison);
+        assert_eq!(
+            sim.step_all(&[Forward]),
+            [PlayerStepResult::PoisonEaten { fatal: false }]
+        );
+        assert_eq!(sim.snake(0).len(), 2);
+        assert!(sim.snake(0).alive);
+
+        let mut sim = game(&format!("poison: 1 2\n{}", level));
+        assert_eq!(
+            sim.step_all(&[Forward]),
+            [PlayerStepResult::PoisonEaten { fatal: true }]
+        );
+        assert!(!sim.snake(0).alive);
+    }
+
+    #[test]
+    fn golden_apples_score_more_and_count_down() {
+        let mut sim = game("golden: 1 5 3\napples: 1,0 0,3\n...\n.^.\n...\n...\n");
+        assert_eq!(sim.apples()[0].kind, FoodKind::Golden);
+        assert_eq!(sim.apples()[0].frames_left, Some(3));
+        assert_eq!(
+            sim.step_all(&[Forward]),
+            [PlayerStepResult::GoldenAppleEaten]
+        );
+        assert_eq!(sim.snake(0).punctuation, 5);
+        assert_eq!(sim.snake(0).len(), 4);
+        // The respawned apple already aged with the frame it spawned on.
+        assert_eq!(sim.apples()[0].frames_left, Some(2));
+    }
+}
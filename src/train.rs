@@ -0,0 +1,147 @@
+use std::{path::PathBuf, time::Instant};
+
+use crate::{
+    agent::Agent,
+    cli::RunSettings,
+    config::Config,
+    env::{SnakeEnv, SnakeGame},
+    rollout::{train_with_workers, RolloutBatch},
+    selfplay::train_self_play,
+    utils::derive_seed,
+    vec_env::VecEnv,
+};
+
+pub struct FinishedGame {
+    pub number: usize,
+    pub score: usize,
+    pub mean_score: f64,
+}
+
+/// Training state shared by the windowed and the headless trainers.
+pub struct TrainingSession {
+    pub agent: Agent,
+    pub record: usize,
+    pub total_score: usize,
+    checkpoint_path: PathBuf,
+    config: Config,
+}
+impl TrainingSession {
+    pub fn new(settings: &RunSettings, config: &Config, env: &impl SnakeEnv) -> Self {
+        Self {
+            agent: Agent::load_if_exists(
+                &settings.model,
+                &config.agent,
+                settings.seed.expect("seed resolved"),
+                env,
+            ),
+            record: 0,
+            total_score: 0,
+            checkpoint_path: settings.checkpoint_path(),
+            config: config.clone(),
+        }
+    }
+
+    fn save_checkpoint(&self) -> Result<(), Box<dyn std::error::Error>> {
+        self.agent.save(&self.checkpoint_path)?;
+        self.config
+            .save(&Config::checkpoint_path(&self.checkpoint_path))
+    }
+
+    /// Plays one step on every game of `envs`, trains on the collected snapshots and returns the
+    /// games that finished, which `envs` already reset.
+    pub fn step<E: SnakeEnv>(&mut self, envs: &mut VecEnv<E>) -> Vec<FinishedGame> {
+        let batch = RolloutBatch::collect(&mut self.agent, envs);
+        self.learn(batch)
+    }
+
+    /// Remembers and trains on the snapshots of one step of every game, saving a checkpoint when
+    /// a finished game beats the record.
+    pub fn learn(&mut self, batch: RolloutBatch) -> Vec<FinishedGame> {
+        let agent = &mut self.agent;
+
+        let snapshots_stored = batch.snapshots.len();
+        for snapshot in batch.snapshots {
+            agent.remember(snapshot);
+        }
+
+        let done_scores = batch.done_scores;
+        let best_score = done_scores.iter().copied().max().unwrap_or(0);
+
+        agent.train_with_last(snapshots_stored);
+
+        let old_n_games = agent.n_games;
+        agent.n_games += done_scores.len();
+
+        if best_score != 0 {
+            agent.train_long_memory();
+
+            if best_score > self.record {
+                self.save_checkpoint().unwrap();
+            }
+        }
+
+        if best_score > self.record {
+            self.record = best_score;
+        }
+
+        let mut finished = Vec::with_capacity(done_scores.len());
+        for (i, score) in done_scores.into_iter().enumerate() {
+            let number = old_n_games + i + 1;
+
+            self.total_score += score;
+            finished.push(FinishedGame {
+                number,
+                score,
+                mean_score: self.total_score as f64 / number as f64,
+            });
+        }
+        finished
+    }
+}
+
+/// Trains against `settings.scenes` games as fast as possible, without opening a window.
+pub fn train_headless(mut settings: RunSettings, workers: Option<usize>, self_play: bool) {
+    let mut config = settings.resolve_config();
+    if let Some(workers) = workers {
+        config.rollout.workers = workers;
+    }
+    if self_play {
+        config.selfplay.enabled = true;
+    }
+    if config.selfplay.enabled {
+        return train_self_play(settings, config);
+    }
+    if config.rollout.workers > 0 {
+        return train_with_workers(settings, config);
+    }
+    let seed = settings.seed.unwrap();
+
+    let mut envs = VecEnv::new(
+        (0..settings.scenes as u64)
+            .map(|index| SnakeGame::new(derive_seed(seed, index), &config))
+            .collect(),
+    );
+    let mut session = TrainingSession::new(&settings, &config, &envs.envs()[0]);
+
+    let started = Instant::now();
+    let mut steps: usize = 0;
+    loop {
+        let finished = session.step(&mut envs);
+        steps += envs.len();
+
+        print_progress(&finished, session.record, steps, started);
+    }
+}
+
+pub fn print_progress(finished: &[FinishedGame], record: usize, steps: usize, started: Instant) {
+    for game in finished {
+        println!(
+            "Game: {}, Score: {}, Record: {}, Mean Score: {:.2}, Steps/s: {:.0}",
+            game.number,
+            game.score,
+            record,
+            game.mean_score,
+            steps as f64 / started.elapsed().as_secs_f64()
+        );
+    }
+}
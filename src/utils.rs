@@ -1,4 +1,9 @@
-use std::collections::VecDeque;
+use std::{
+    collections::{HashMap, VecDeque},
+    hash::Hash,
+};
+
+use rand::Rng;
 
 pub struct FixedVecDeque<T> {
     deque: VecDeque<T>,
@@ -28,3 +33,107 @@ impl<T> FixedVecDeque<T> {
         self.deque.len()
     }
 }
+
+/// Set with O(1) insertion, removal and uniform sampling. The sampling order only depends on the
+/// sequence of operations, so it stays deterministic for seeded random generators.
+pub struct IndexedSet<T> {
+    values: Vec<T>,
+    indices: HashMap<T, usize>,
+}
+
+impl<T: Copy + Eq + Hash> IndexedSet<T> {
+    pub fn new() -> Self {
+        Self {
+            values: Vec::new(),
+            indices: HashMap::new(),
+        }
+    }
+
+    pub fn insert(&mut self, value: T) {
+        if !self.indices.contains_key(&value) {
+            self.indices.insert(value, self.values.len());
+            self.values.push(value);
+        }
+    }
+
+    pub fn remove(&mut self, value: &T) {
+        if let Some(index) = self.indices.remove(value) {
+            self.values.swap_remove(index);
+            if let Some(moved) = self.values.get(index) {
+                self.indices.insert(*moved, index);
+            }
+        }
+    }
+
+    pub fn contains(&self, value: &T) -> bool {
+        self.indices.contains_key(value)
+    }
+
+    pub fn clear(&mut self) {
+        self.values.clear();
+        self.indices.clear();
+    }
+
+    pub fn choose(&self, rng: &mut impl Rng) -> Option<T> {
+        if self.values.is_empty() {
+            None
+        } else {
+            Some(self.values[rng.gen_range(0..self.values.len())])
+        }
+    }
+}
+
+/// Derives the seed of one random stream (a game, the agent...) from the seed of the run, so
+/// that streams sharing a run seed do not produce the same numbers.
+pub fn derive_seed(seed: u64, stream: u64) -> u64 {
+    // SplitMix64 finalizer.
+    let mut z = seed ^ stream.wrapping_add(1).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+
+    #[test]
+    fn indexed_set_keeps_each_value_once() {
+        let mut set = IndexedSet::new();
+        for value in [1, 2, 3, 2] {
+            set.insert(value);
+        }
+        assert_eq!(set.values.len(), 3);
+
+        set.remove(&1);
+        set.remove(&1);
+        assert!(!set.indices.contains_key(&1));
+        // The last value moved into the removed slot is still indexed.
+        set.remove(&3);
+        assert_eq!(set.values, [2]);
+        assert_eq!(set.indices[&2], 0);
+    }
+
+    #[test]
+    fn indexed_set_chooses_among_its_values() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut set = IndexedSet::new();
+        assert_eq!(set.choose(&mut rng), None);
+
+        for value in 0..10 {
+            set.insert(value);
+        }
+        for value in (0..10).step_by(2) {
+            set.remove(&value);
+        }
+        for _ in 0..100 {
+            let chosen = set.choose(&mut rng).unwrap();
+            assert!(chosen % 2 == 1 && set.values.contains(&chosen));
+        }
+
+        set.clear();
+        assert_eq!(set.choose(&mut rng), None);
+    }
+}
@@ -0,0 +1,84 @@
+use tch::Tensor;
+
+use crate::{
+    env::{Observation, SnakeEnv, StepInfo},
+    model::REWARD,
+};
+
+pub struct VecStep {
+    /// Observation each game reached with its action, before any reset.
+    pub final_observations: Vec<Observation>,
+    pub rewards: Vec<REWARD>,
+    pub dones: Vec<bool>,
+    pub infos: Vec<StepInfo>,
+}
+
+/// Steps N environments together, resetting the finished ones automatically, so that actions
+/// for every game are chosen with a single batched forward pass.
+pub struct VecEnv<E> {
+    envs: Vec<E>,
+    observations: Vec<Observation>,
+}
+
+impl<E: SnakeEnv> VecEnv<E> {
+    pub fn new(envs: Vec<E>) -> Self {
+        let observations = envs.iter().map(|env| env.observation()).collect();
+        Self { envs, observations }
+    }
+
+    pub fn envs(&self) -> &[E] {
+        &self.envs
+    }
+
+    pub fn len(&self) -> usize {
+        self.envs.len()
+    }
+
+    /// Current observation of each game.
+    pub fn observations(&self) -> &[Observation] {
+        &self.observations
+    }
+
+    /// Current observations as a `[N, observation size]` tensor.
+    pub fn observation_tensor(&self) -> Tensor {
+        Tensor::from_slice(&self.observations.concat()).view([self.len() as i64, -1])
+    }
+
+    /// Steps the game `i` with `actions[i]`. Finished games are reset, so the next
+    /// [`VecEnv::observation_tensor`] is ready to act on.
+    pub fn step(&mut self, actions: &[usize]) -> VecStep {
+        assert_eq!(actions.len(), self.len(), "One action is needed per game.");
+
+        let mut final_observations = Vec::with_capacity(self.len());
+        let mut rewards = Vec::with_capacity(self.len());
+        let mut dones = Vec::with_capacity(self.len());
+        let mut infos = Vec::with_capacity(self.len());
+
+        for ((env, observation), action) in self
+            .envs
+            .iter_mut()
+            .zip(self.observations.iter_mut())
+            .zip(actions)
+        {
+            let step = env.step(*action);
+
+            *observation = if step.done {
+                env.reset()
+            } else {
+                step.observation.clone()
+            };
+
+            final_observations.push(step.observation);
+            rewards.push(step.reward);
+            dones.push(step.done);
+            infos.push(step.info);
+        }
+
+        VecStep {
+            final_observations,
+            rewards,
+            dones,
+            infos,
+        }
+    }
+}